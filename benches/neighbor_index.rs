@@ -0,0 +1,58 @@
+// Compares the build and k-NN query times of the spatial index backends, and the
+// recall of the approximate backend.
+// Run with `cargo bench --bench neighbor_index`.
+use pointpca2_rs::knn_search;
+use pointpca2_rs::neighbor_index::{self, IndexBackend};
+use rayon::prelude::*;
+use std::time::Instant;
+
+// Voxelized sphere surface, similar to MPEG voxelized content
+fn voxelized_sphere(radius: f64) -> Vec<[f64; 3]> {
+    let r = radius as i64 + 1;
+    let mut points = Vec::new();
+    for x in -r..=r {
+        for y in -r..=r {
+            for z in -r..=r {
+                let norm = ((x * x + y * y + z * z) as f64).sqrt();
+                if (norm - radius).abs() < 0.5 {
+                    points.push([x as f64, y as f64, z as f64]);
+                }
+            }
+        }
+    }
+    points
+}
+
+fn main() {
+    let k = 81;
+    for radius in [50., 150.] {
+        let points = voxelized_sphere(radius);
+        println!("{} points, k = {}", points.len(), k);
+        let backends = [
+            IndexBackend::KdTree,
+            IndexBackend::VoxelGrid { cell_size: None },
+            IndexBackend::ApproximateKdTree { max_checks: 128 },
+            IndexBackend::ApproximateKdTree { max_checks: 256 },
+        ];
+        for backend in backends {
+            let start = Instant::now();
+            let index = neighbor_index::build_index(&points, &backend);
+            let build_secs = start.elapsed().as_secs_f64();
+            let start = Instant::now();
+            let checksum = points
+                .par_iter()
+                .map(|point| index.knn(point, k).len())
+                .sum::<usize>();
+            let query_secs = start.elapsed().as_secs_f64();
+            assert_eq!(checksum, points.len() * k);
+            let recall = knn_search::measure_recall(&points, &points, &backend, k, 1000, 0);
+            println!(
+                "  {:<46} build {:>9.3} ms  query {:>9.3} ms  recall {:.4}",
+                format!("{:?}", backend),
+                build_secs * 1e3,
+                query_secs * 1e3,
+                recall.recall
+            );
+        }
+    }
+}
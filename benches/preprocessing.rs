@@ -0,0 +1,102 @@
+// Compares the parallel sort-based deduplication of `preprocess_point_cloud`
+// against the previous single-threaded BTreeMap implementation.
+// Run with `cargo bench --bench preprocessing`.
+use ordered_float::OrderedFloat;
+use pointpca2_rs::preprocessing;
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+type OrderedPoint = (OrderedFloat<f64>, OrderedFloat<f64>, OrderedFloat<f64>);
+
+fn rgb_to_yuv(rgb: [u8; 3]) -> [u8; 3] {
+    let r = rgb[0] as f64;
+    let g = rgb[1] as f64;
+    let b = rgb[2] as f64;
+    let y = (0.2126 * r + 0.2126 * g + 0.0722 * b) + 0.;
+    let u = (-0.1146 * r + -0.3854 * g + 0.5000 * b) + 128.;
+    let v = (0.5000 * r + -0.4542 * g + -0.0468 * b) + 128.;
+    [
+        y.round().clamp(0.0, 255.0) as u8,
+        u.round().clamp(0.0, 255.0) as u8,
+        v.round().clamp(0.0, 255.0) as u8,
+    ]
+}
+
+fn btree_preprocess_point_cloud(
+    points: Vec<[f64; 3]>,
+    colors: Vec<[u8; 3]>,
+) -> (Vec<[f64; 3]>, Vec<[u8; 3]>) {
+    let mut points_map: BTreeMap<OrderedPoint, Vec<[u8; 3]>> = BTreeMap::new();
+    for (point, color) in points.into_iter().zip(colors) {
+        let key = (
+            OrderedFloat(point[0]),
+            OrderedFloat(point[1]),
+            OrderedFloat(point[2]),
+        );
+        points_map.entry(key).or_default().push(color);
+    }
+    points_map
+        .into_iter()
+        .map(|(key, colors)| {
+            let len = colors.len() as f64;
+            let mut sum = [0.; 3];
+            for color in colors {
+                for c in 0..3 {
+                    sum[c] += color[c] as f64;
+                }
+            }
+            let mean = sum.map(|s| (s / len).round() as u8);
+            ([key.0.into(), key.1.into(), key.2.into()], rgb_to_yuv(mean))
+        })
+        .unzip()
+}
+
+// Voxelized cloud where roughly a third of the points are duplicates
+fn synthetic_cloud(npoints: usize) -> (Vec<[f64; 3]>, Vec<[u8; 3]>) {
+    let mut state: u64 = 0x853c49e6748fea9b;
+    let mut next = || {
+        state = state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (state >> 33) as u32
+    };
+    let grid = (npoints as f64 * 1.5).cbrt() as u32 + 1;
+    (0..npoints)
+        .map(|_| {
+            let point = [0, 1, 2].map(|_| (next() % grid) as f64);
+            let color = [0, 1, 2].map(|_| (next() % 256) as u8);
+            (point, color)
+        })
+        .unzip()
+}
+
+fn time<T>(name: &str, runs: usize, mut f: impl FnMut() -> T) -> (T, f64) {
+    let mut best = f64::INFINITY;
+    let mut result = None;
+    for _ in 0..runs {
+        let start = Instant::now();
+        result = Some(f());
+        best = best.min(start.elapsed().as_secs_f64());
+    }
+    println!("  {:<10} {:>9.3} ms", name, best * 1e3);
+    (result.unwrap(), best)
+}
+
+fn main() {
+    for npoints in [100_000, 1_000_000, 5_000_000] {
+        let (points, colors) = synthetic_cloud(npoints);
+        println!("{} points", npoints);
+        let (expected, btree_secs) = time("btree", 3, || {
+            btree_preprocess_point_cloud(points.clone(), colors.clone())
+        });
+        let (result, parallel_secs) = time("parallel", 3, || {
+            preprocessing::preprocess_point_cloud(points.clone(), colors.clone())
+        });
+        assert!(expected == result, "Deduplication results differ.");
+        println!(
+            "  {} unique points, speedup {:.2}x",
+            result.0.len(),
+            btree_secs / parallel_secs
+        );
+    }
+}
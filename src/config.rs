@@ -0,0 +1,81 @@
+use crate::execution::{CancellationToken, Threads};
+use crate::knn_search::{Neighborhood, SmallCloudPolicy};
+use crate::neighbor_index::IndexBackend;
+use crate::pca::{CovarianceOptions, DegeneratePolicy};
+use crate::precision::Precision;
+use crate::predictors::{Attributes, PredictorRegistry};
+use crate::preprocessing::OutlierFilter;
+use crate::progress::{NoProgress, ProgressSink};
+use crate::sampling::{Bootstrap, QuerySampling};
+use std::sync::Arc;
+
+#[derive(Clone, Debug)]
+pub struct PointPca2Config {
+    pub neighborhood: Neighborhood,
+    // Spatial index used for the neighborhood queries of the feature stage
+    pub index_backend: IndexBackend,
+    pub small_cloud_policy: SmallCloudPolicy,
+    // Weighting, estimator and normalization of the local covariance matrices
+    pub covariance: CovarianceOptions,
+    // Handling of rank-deficient (planar, linear or degenerate) neighborhoods
+    pub degenerate_policy: DegeneratePolicy,
+    // Floating-point type of the local PCA, local features and per-point predictors
+    pub precision: Precision,
+    // Per-point predictors computed from the local features and pooled, in output order
+    pub predictors: PredictorRegistry,
+    // Attributes compared. Geometry or texture alone skips the work of the other attribute and
+    // keeps only the predictors of that attribute
+    pub attributes: Attributes,
+    // Receives stage timings, point counts and messages about the run
+    pub progress: Arc<dyn ProgressSink>,
+    // Outlier filters for the reference (a) and distorted (b) clouds, applied after preprocessing
+    pub outlier_filter_a: Option<OutlierFilter>,
+    pub outlier_filter_b: Option<OutlierFilter>,
+    // Collect a PreprocessReport for each cloud
+    pub preprocess_report: bool,
+    // Bytes available for the local features of the reference points processed at once.
+    // When set, features are computed in chunks and pooled on the fly instead of being
+    // materialized for the whole reference cloud
+    pub memory_budget: Option<usize>,
+    // Reference points at which the local features are computed and pooled
+    pub sampling: QuerySampling,
+    // Confidence intervals of the pooled predictors, from the per-point predictors
+    pub bootstrap: Option<Bootstrap>,
+    // Rayon pool running the computation
+    pub threads: Threads,
+    // Aborts the run with PointPca2Error::Cancelled once triggered
+    pub cancellation: Option<CancellationToken>,
+}
+
+impl PointPca2Config {
+    pub fn new(search_size: usize) -> Self {
+        Self {
+            neighborhood: Neighborhood::Knn(search_size),
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for PointPca2Config {
+    fn default() -> Self {
+        Self {
+            neighborhood: Neighborhood::Knn(81),
+            index_backend: IndexBackend::KdTree,
+            small_cloud_policy: SmallCloudPolicy::Clamp,
+            covariance: CovarianceOptions::default(),
+            degenerate_policy: DegeneratePolicy::Keep,
+            precision: Precision::F64,
+            predictors: PredictorRegistry::builtin(),
+            attributes: Attributes::Both,
+            progress: Arc::new(NoProgress),
+            outlier_filter_a: None,
+            outlier_filter_b: None,
+            preprocess_report: false,
+            memory_budget: None,
+            sampling: QuerySampling::All,
+            bootstrap: None,
+            threads: Threads::Global,
+            cancellation: None,
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PointPca2Error {
+    // A cloud had no points left after preprocessing
+    EmptyCloud {
+        cloud: &'static str,
+    },
+    // A cloud had fewer points than the neighborhood size under SmallCloudPolicy::Error
+    CloudSmallerThanNeighborhood {
+        cloud: &'static str,
+        points: usize,
+        neighborhood_size: usize,
+    },
+    // Every neighborhood was excluded from pooling under DegeneratePolicy::Skip
+    AllNeighborhoodsSkipped {
+        points: usize,
+    },
+    // A multi-scale run was given no neighborhood size
+    NoScales,
+    // No predictor of the registry covers the attributes of the config
+    NoPredictors,
+    // A predictor was registered under the name of a registered one
+    DuplicatePredictor {
+        name: String,
+    },
+    // A predictor returned a number of columns other than its width
+    PredictorWidthMismatch {
+        name: String,
+        width: usize,
+        columns: usize,
+    },
+    // A neighborhood or sampling option of the config is out of its range
+    InvalidConfig {
+        message: String,
+    },
+    // The cancellation token of the config was triggered
+    Cancelled,
+    // The dedicated thread pool could not be built
+    ThreadPool {
+        message: String,
+    },
+    // Bytes that do not decode to a reduced reference signature
+    InvalidSignature {
+        message: String,
+    },
+}
+
+impl fmt::Display for PointPca2Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::EmptyCloud { cloud } => write!(f, "The {} cloud is empty.", cloud),
+            Self::CloudSmallerThanNeighborhood {
+                cloud,
+                points,
+                neighborhood_size,
+            } => write!(
+                f,
+                "The {} cloud has {} points, fewer than the neighborhood size of {}.",
+                cloud, points, neighborhood_size
+            ),
+            Self::AllNeighborhoodsSkipped { points } => write!(
+                f,
+                "All {} neighborhoods are degenerate and were skipped from pooling.",
+                points
+            ),
+            Self::NoScales => write!(f, "No neighborhood size was given for the scales."),
+            Self::NoPredictors => write!(f, "No predictor covers the selected attributes."),
+            Self::DuplicatePredictor { name } => {
+                write!(f, "A predictor named {} is already registered.", name)
+            }
+            Self::PredictorWidthMismatch {
+                name,
+                width,
+                columns,
+            } => write!(
+                f,
+                "Predictor {} returned {} columns instead of its width of {}.",
+                name, columns, width
+            ),
+            Self::InvalidConfig { message } => write!(f, "Invalid configuration: {}", message),
+            Self::Cancelled => write!(f, "The computation was cancelled."),
+            Self::ThreadPool { message } => {
+                write!(f, "The thread pool could not be built: {}", message)
+            }
+            Self::InvalidSignature { message } => {
+                write!(f, "Invalid reduced reference signature: {}", message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PointPca2Error {}
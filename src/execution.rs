@@ -0,0 +1,66 @@
+use crate::error::PointPca2Error;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+// Rayon pool the computation runs on
+#[derive(Clone, Debug, Default)]
+pub enum Threads {
+    // The global rayon pool
+    #[default]
+    Global,
+    // A dedicated pool with this number of threads, built for each run
+    Count(usize),
+    // An existing pool, shared with other workloads of the caller
+    Pool(Arc<ThreadPool>),
+}
+
+impl Threads {
+    pub fn install<R: Send>(&self, op: impl FnOnce() -> R + Send) -> Result<R, PointPca2Error> {
+        match self {
+            Threads::Global => Ok(op()),
+            Threads::Count(num_threads) => {
+                let pool = ThreadPoolBuilder::new()
+                    .num_threads(*num_threads)
+                    .build()
+                    .map_err(|error| PointPca2Error::ThreadPool {
+                        message: error.to_string(),
+                    })?;
+                Ok(pool.install(op))
+            }
+            Threads::Pool(pool) => Ok(pool.install(op)),
+        }
+    }
+}
+
+// Shared flag to abort a run from another thread. It is checked between chunks of query
+// points, so a cancelled run stops after the chunks in progress
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+pub fn is_cancelled(cancellation: &Option<CancellationToken>) -> bool {
+    cancellation
+        .as_ref()
+        .is_some_and(|token| token.is_cancelled())
+}
+
+pub fn check_cancelled(cancellation: &Option<CancellationToken>) -> Result<(), PointPca2Error> {
+    if is_cancelled(cancellation) {
+        return Err(PointPca2Error::Cancelled);
+    }
+    Ok(())
+}
@@ -1,11 +1,598 @@
-use crate::knn_search;
-use crate::pca;
-use crate::utils;
-use na::DMatrix;
+use crate::config::PointPca2Config;
+use crate::execution::{self, CancellationToken};
+use crate::knn_search::{self, KnnGraph, Neighborhood, SmallCloudPolicy};
+use crate::neighbor_index::{self, NeighborIndex};
+use crate::pca::{self, CovarianceOptions, DegeneratePolicy, NeighborhoodClass};
+use crate::precision::{Precision, Real};
+use crate::predictors::Attributes;
+use crate::progress::{ProgressSink, Stage, StageTimer};
+use crate::sampling::{self, QuerySampling};
+use na::{DMatrix, Dyn, Matrix3, MatrixViewMut, Vector3, U1};
 use rayon::iter::{IndexedParallelIterator, IntoParallelRefMutIterator, ParallelIterator};
+use std::borrow::Cow;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
 const FEATURES_DIMENSION: usize = 42;
+// Number of query points whose kNN graphs are held in memory at once
+const QUERY_CHUNK_SIZE: usize = 16384;
+// Number of processed query points between two progress reports
+const PROGRESS_INTERVAL: usize = 1024;
 
+type FeaturesRow<'a, T = f64> = MatrixViewMut<'a, T, U1, Dyn, U1, Dyn>;
+
+struct Cloud<'a> {
+    points: &'a [[f64; 3]],
+    colors: &'a [[u8; 3]],
+    index: Box<dyn NeighborIndex<'a> + 'a>,
+}
+
+// Neighborhood buffers reused across the points handled by a thread, so that the kernel
+// does not allocate once they have grown to the neighborhood size
+struct Scratch<T> {
+    points_a: Vec<Vector3<T>>,
+    colors_a: Vec<Vector3<T>>,
+    points_b: Vec<Vector3<T>>,
+    colors_b: Vec<Vector3<T>>,
+}
+
+impl<T> Default for Scratch<T> {
+    fn default() -> Self {
+        Self {
+            points_a: Vec::new(),
+            colors_a: Vec::new(),
+            points_b: Vec::new(),
+            colors_b: Vec::new(),
+        }
+    }
+}
+
+// Rows and classes computed from the first k neighbors of the shared kNN graph
+struct ScaleRows<'m, T> {
+    k: usize,
+    rows: Vec<FeaturesRow<'m, T>>,
+    classes: Vec<NeighborhoodClass>,
+}
+
+// Counts the processed query points, reporting them every PROGRESS_INTERVAL points
+struct PointCounter {
+    progress: Arc<dyn ProgressSink>,
+    processed: AtomicUsize,
+    total: usize,
+}
+
+impl PointCounter {
+    fn add(&self, count: usize) {
+        let before = self.processed.fetch_add(count, Ordering::Relaxed);
+        let after = before + count;
+        if after / PROGRESS_INTERVAL > before / PROGRESS_INTERVAL || after == self.total {
+            self.progress.points_processed(after, self.total);
+        }
+    }
+}
+
+struct Kernel<'a> {
+    cloud_a: Cloud<'a>,
+    cloud_b: Cloud<'a>,
+    neighborhood: Neighborhood,
+    attributes: Attributes,
+    // Whether short neighborhoods are padded to the neighborhood size
+    pad: bool,
+    covariance: CovarianceOptions,
+    degenerate_policy: DegeneratePolicy,
+    cancellation: Option<CancellationToken>,
+    counter: PointCounter,
+}
+
+// Neighborhoods shorter than pad_size are filled by repeating their neighbors
+fn padded(indices: &[usize], pad_size: Option<usize>) -> Cow<'_, [usize]> {
+    match pad_size {
+        Some(size) if !indices.is_empty() && indices.len() < size => {
+            Cow::Owned(knn_search::pad_neighbors(indices, size))
+        }
+        _ => Cow::Borrowed(indices),
+    }
+}
+
+// Points are converted relative to origin, see Real::from_coordinate
+fn gather_points<T: Real>(
+    cloud: &Cloud,
+    knn_indices: &[usize],
+    origin: &[f64; 3],
+    points: &mut Vec<Vector3<T>>,
+) {
+    points.clear();
+    for &i in knn_indices {
+        let point = &cloud.points[i];
+        points.push(Vector3::from_fn(|j, _| {
+            T::from_coordinate(point[j], origin[j])
+        }));
+    }
+}
+
+fn gather_colors<T: Real>(cloud: &Cloud, knn_indices: &[usize], colors: &mut Vec<Vector3<T>>) {
+    colors.clear();
+    for &i in knn_indices {
+        colors.push(Vector3::from(cloud.colors[i].map(|c| T::cast(c as f64))));
+    }
+}
+
+// Summed in row order, like the row means of nalgebra
+fn row_mean<T: Real>(rows: &[Vector3<T>]) -> Vector3<T> {
+    let mut sum = Vector3::zeros();
+    for row in rows {
+        sum += row;
+    }
+    sum / T::cast(rows.len() as f64)
+}
+
+// Centers the rows on mean and expresses them in the basis given by the columns of axes
+fn project<T: Real>(rows: &mut [Vector3<T>], mean: &Vector3<T>, axes: &Matrix3<T>) {
+    for row in rows {
+        let centered = *row - mean;
+        *row = Vector3::from_fn(|j, _| {
+            centered[0] * axes[(0, j)] + centered[1] * axes[(1, j)] + centered[2] * axes[(2, j)]
+        });
+    }
+}
+
+// Means, variances and covariance of one attribute (projected geometry or colors) of the
+// reference and distorted neighborhoods
+struct Moments<T> {
+    mean_a: Vector3<T>,
+    mean_b: Vector3<T>,
+    variance_a: Vector3<T>,
+    variance_b: Vector3<T>,
+    covariance_ab: Vector3<T>,
+}
+
+fn variances<T: Real>(rows: &[Vector3<T>], mean: &Vector3<T>) -> Vector3<T> {
+    let mut sum = Vector3::zeros();
+    for row in rows {
+        sum += (row - mean).map(|x| x.powi(2));
+    }
+    sum / T::cast(rows.len() as f64)
+}
+
+fn moments<T: Real>(rows_a: &[Vector3<T>], rows_b: &[Vector3<T>]) -> Moments<T> {
+    let (mean_a, mean_b) = (row_mean(rows_a), row_mean(rows_b));
+    // Neighborhoods may differ in size, so only the neighbors ranked in both are paired
+    let common_rows = rows_a.len().min(rows_b.len());
+    let mut covariance_ab = Vector3::zeros();
+    for i in 0..common_rows {
+        covariance_ab += (rows_a[i] - mean_a).component_mul(&(rows_b[i] - mean_b));
+    }
+    covariance_ab /= T::cast(common_rows as f64);
+    Moments {
+        variance_a: variances(rows_a, &mean_a),
+        variance_b: variances(rows_b, &mean_b),
+        mean_a,
+        mean_b,
+        covariance_ab,
+    }
+}
+
+// Texture columns of the local features, see predictors::LocalFeatures for the layout
+fn write_texture<T: Real>(row: &mut FeaturesRow<T>, texture: &Moments<T>) {
+    row.columns_mut(6, 3).copy_from(&texture.mean_a.transpose());
+    row.columns_mut(12, 3)
+        .copy_from(&texture.mean_b.transpose());
+    row.columns_mut(18, 3)
+        .copy_from(&texture.variance_a.transpose());
+    row.columns_mut(24, 3)
+        .copy_from(&texture.variance_b.transpose());
+    row.columns_mut(30, 3)
+        .copy_from(&texture.covariance_ab.transpose());
+}
+
+// Geometry columns of the local features, from the projected neighborhoods and the axes of
+// the projected distorted neighborhood
+fn write_geometry<T: Real>(
+    row: &mut FeaturesRow<T>,
+    projection_a_to_a: &[Vector3<T>],
+    projection_b_to_a: &[Vector3<T>],
+    geometry: &Moments<T>,
+    eigenvectors_b: &Matrix3<T>,
+) {
+    row.columns_mut(0, 3)
+        .copy_from(&projection_a_to_a[0].transpose());
+    row.columns_mut(3, 3)
+        .copy_from(&projection_b_to_a[0].transpose());
+    row.columns_mut(9, 3)
+        .copy_from(&geometry.mean_b.transpose());
+    row.columns_mut(15, 3)
+        .copy_from(&geometry.variance_a.transpose());
+    row.columns_mut(21, 3)
+        .copy_from(&geometry.variance_b.transpose());
+    row.columns_mut(27, 3)
+        .copy_from(&geometry.covariance_ab.transpose());
+    row.columns_mut(33, 3)
+        .copy_from(&eigenvectors_b.column(0).transpose());
+    row.columns_mut(36, 3)
+        .copy_from(&eigenvectors_b.column(1).transpose());
+    row.columns_mut(39, 3)
+        .copy_from(&eigenvectors_b.column(2).transpose());
+}
+
+impl Kernel<'_> {
+    fn is_cancelled(&self) -> bool {
+        execution::is_cancelled(&self.cancellation)
+    }
+
+    fn compute_point_features<T: Real>(
+        &self,
+        row: &mut FeaturesRow<T>,
+        scratch: &mut Scratch<T>,
+        origin: &[f64; 3],
+        knn_indices_a: &[usize],
+        knn_indices_b: &[usize],
+    ) -> NeighborhoodClass {
+        let Scratch {
+            points_a,
+            colors_a,
+            points_b,
+            colors_b,
+        } = scratch;
+        // Colors are compared neighbor by neighbor, regardless of the geometry
+        if self.attributes.includes_texture() {
+            gather_colors(&self.cloud_a, knn_indices_a, colors_a);
+            gather_colors(&self.cloud_b, knn_indices_b, colors_b);
+            write_texture(row, &moments(colors_a, colors_b));
+        }
+        // The class only matters to the geometry and to the other degenerate policies
+        if !self.attributes.includes_geometry() && self.degenerate_policy == DegeneratePolicy::Keep
+        {
+            return NeighborhoodClass::Unclassified;
+        }
+        // Principal components of reference data (new orthonormal basis), the first reference
+        // neighbor being the query point itself
+        gather_points(&self.cloud_a, knn_indices_a, origin, points_a);
+        let query = points_a[0];
+        let (eigenvalues_a, eigenvectors_a) =
+            pca::compute_pca3_with_options(points_a, &query, &self.covariance);
+        let class_a = pca::classify_neighborhood(&eigenvalues_a, points_a);
+        // Without geometry the distorted neighborhood needs no PCA, and the class of the point
+        // is the class of its reference neighborhood
+        if !self.attributes.includes_geometry() {
+            return class_a;
+        }
+        // Project reference and distorted data onto the new orthonormal basis, in place
+        gather_points(&self.cloud_b, knn_indices_b, origin, points_b);
+        let points_a_mean = row_mean(points_a);
+        project(points_a, &points_a_mean, &eigenvectors_a);
+        project(points_b, &points_a_mean, &eigenvectors_a);
+        let (projection_a_to_a, projection_b_to_a) = (&points_a[..], &points_b[..]);
+        let geometry = moments(projection_a_to_a, projection_b_to_a);
+        // Principal components of projected distorted data
+        let (eigenvalues_b, eigenvectors_b) = pca::compute_pca3_with_options(
+            projection_b_to_a,
+            &projection_a_to_a[0],
+            &self.covariance,
+        );
+        let class_b = pca::classify_neighborhood(&eigenvalues_b, projection_b_to_a);
+        write_geometry(
+            row,
+            projection_a_to_a,
+            projection_b_to_a,
+            &geometry,
+            &eigenvectors_b,
+        );
+        // A point is as degenerate as the worse of its two neighborhoods
+        class_a.max(class_b)
+    }
+
+    fn point_features<T: Real>(
+        &self,
+        row: &mut FeaturesRow<T>,
+        scratch: &mut Scratch<T>,
+        query: &[f64; 3],
+        knn_indices_a: &[usize],
+        knn_indices_b: &[usize],
+        size: usize,
+    ) -> NeighborhoodClass {
+        let pad_size = self.pad.then_some(size);
+        let mut class = self.compute_point_features(
+            row,
+            scratch,
+            query,
+            &padded(knn_indices_a, pad_size),
+            &padded(knn_indices_b, pad_size),
+        );
+        if let DegeneratePolicy::Expand { from, max_k } = self.degenerate_policy {
+            let mut sizes = (knn_indices_a.len(), knn_indices_b.len());
+            let mut k = sizes.0.max(sizes.1);
+            while class >= from && k < max_k {
+                k = (2 * k).clamp(1, max_k);
+                let knn_indices_a = knn_search::nearest_n(self.cloud_a.index.as_ref(), query, k);
+                let knn_indices_b = knn_search::nearest_n(self.cloud_b.index.as_ref(), query, k);
+                // Both clouds are exhausted, a larger k cannot help
+                if (knn_indices_a.len(), knn_indices_b.len()) == sizes {
+                    break;
+                }
+                sizes = (knn_indices_a.len(), knn_indices_b.len());
+                class = self.compute_point_features(
+                    row,
+                    scratch,
+                    query,
+                    &knn_indices_a,
+                    &knn_indices_b,
+                );
+            }
+        }
+        class
+    }
+
+    // Queries the kNN graphs once at the largest k of the scales, each scale using the
+    // prefix of the sorted neighbor lists that a query at its own k would have returned
+    fn compute_knn_features<T: Real>(&self, scales: &mut [ScaleRows<T>], queries: &[[f64; 3]]) {
+        let k_max = scales.iter().map(|scale| scale.k).max().unwrap_or(0);
+        let chunk_size = QUERY_CHUNK_SIZE.min(queries.len());
+        let mut graph_a = KnnGraph::new(chunk_size, k_max, false);
+        let mut graph_b = KnnGraph::new(chunk_size, k_max, false);
+        for (start, queries) in (0..)
+            .step_by(QUERY_CHUNK_SIZE)
+            .zip(queries.chunks(QUERY_CHUNK_SIZE))
+        {
+            if self.is_cancelled() {
+                return;
+            }
+            graph_a.fill(self.cloud_a.index.as_ref(), queries);
+            graph_b.fill(self.cloud_b.index.as_ref(), queries);
+            for scale in scales.iter_mut() {
+                let k = scale.k;
+                let end = start + queries.len();
+                scale.rows[start..end]
+                    .par_iter_mut()
+                    .zip(scale.classes[start..end].par_iter_mut())
+                    .enumerate()
+                    .for_each_init(Scratch::default, |scratch, (i, (row, class))| {
+                        let knn_indices_a = graph_a.neighbors(i);
+                        let knn_indices_b = graph_b.neighbors(i);
+                        *class = self.point_features(
+                            row,
+                            scratch,
+                            &queries[i],
+                            &knn_indices_a[..k.min(knn_indices_a.len())],
+                            &knn_indices_b[..k.min(knn_indices_b.len())],
+                            k,
+                        );
+                        self.counter.add(1);
+                    });
+            }
+        }
+    }
+
+    // Local features and neighborhood classes of the given reference points at every
+    // neighborhood size of ks
+    fn compute_knn_rows<T: Real>(
+        &self,
+        queries: &[[f64; 3]],
+        ks: &[usize],
+    ) -> Vec<(DMatrix<T>, Vec<NeighborhoodClass>)> {
+        let nrows = queries.len();
+        let mut local_features = vec![DMatrix::zeros(nrows, FEATURES_DIMENSION); ks.len()];
+        let mut scales = local_features
+            .iter_mut()
+            .zip(ks)
+            .map(|(features, &k)| ScaleRows {
+                k,
+                rows: features.row_iter_mut().collect(),
+                classes: vec![NeighborhoodClass::FullRank; nrows],
+            })
+            .collect::<Vec<_>>();
+        self.compute_knn_features(&mut scales, queries);
+        let classes = scales
+            .into_iter()
+            .map(|scale| scale.classes)
+            .collect::<Vec<_>>();
+        local_features.into_iter().zip(classes).collect()
+    }
+
+    // Local features and neighborhood classes of the given reference points
+    fn compute_rows<T: Real>(&self, queries: &[[f64; 3]]) -> (DMatrix<T>, Vec<NeighborhoodClass>) {
+        let neighborhood = match self.neighborhood {
+            // Fixed-size neighborhoods are queried in batches into reusable flat buffers
+            Neighborhood::Knn(k) => {
+                let mut scales = self.compute_knn_rows(queries, &[k]);
+                return scales.pop().expect("One scale was requested");
+            }
+            ref neighborhood => neighborhood,
+        };
+        let nrows = queries.len();
+        let mut local_features = DMatrix::zeros(nrows, FEATURES_DIMENSION);
+        let mut rows = local_features.row_iter_mut().collect::<Vec<_>>();
+        let mut classes = vec![NeighborhoodClass::FullRank; nrows];
+        rows.par_iter_mut()
+            .zip(classes.par_iter_mut())
+            .enumerate()
+            .for_each_init(Scratch::default, |scratch, (i, (row, class))| {
+                if self.is_cancelled() {
+                    return;
+                }
+                let query = &queries[i];
+                let knn_indices_a =
+                    knn_search::neighborhood(self.cloud_a.index.as_ref(), query, neighborhood);
+                let knn_indices_b =
+                    knn_search::neighborhood(self.cloud_b.index.as_ref(), query, neighborhood);
+                *class = self.point_features(
+                    row,
+                    scratch,
+                    query,
+                    &knn_indices_a,
+                    &knn_indices_b,
+                    neighborhood.size(),
+                );
+                self.counter.add(1);
+            });
+        (local_features, classes)
+    }
+}
+
+impl<'a> Kernel<'a> {
+    // Builds the neighbor indexes of both clouds, total_points being the number of query
+    // points reported as progress
+    fn new(
+        points_a: &'a [[f64; 3]],
+        colors_a: &'a [[u8; 3]],
+        points_b: &'a [[f64; 3]],
+        colors_b: &'a [[u8; 3]],
+        config: &PointPca2Config,
+        total_points: usize,
+    ) -> Self {
+        let mut timer = StageTimer::new(&config.progress, Stage::IndexBuild);
+        let (index_a, index_b) = timer.time(|| {
+            (
+                neighbor_index::build_index(points_a, &config.index_backend),
+                neighbor_index::build_index(points_b, &config.index_backend),
+            )
+        });
+        timer.finish();
+        Kernel {
+            cloud_a: Cloud {
+                points: points_a,
+                colors: colors_a,
+                index: index_a,
+            },
+            cloud_b: Cloud {
+                points: points_b,
+                colors: colors_b,
+                index: index_b,
+            },
+            neighborhood: config.neighborhood,
+            attributes: config.attributes,
+            pad: config.small_cloud_policy == SmallCloudPolicy::Pad,
+            covariance: config.covariance,
+            degenerate_policy: config.degenerate_policy,
+            cancellation: config.cancellation.clone(),
+            counter: PointCounter {
+                progress: config.progress.clone(),
+                processed: AtomicUsize::new(0),
+                total: total_points,
+            },
+        }
+    }
+
+    // Computes the chunks of queries in order and hands them to consume, stopping without
+    // consuming the chunk in progress once the run is cancelled. The features stage ends
+    // before the last chunk is consumed
+    fn run_chunks<T>(
+        &self,
+        queries: &[[f64; 3]],
+        chunk_size: usize,
+        compute: impl Fn(&Self, &[[f64; 3]]) -> T,
+        mut consume: impl FnMut(T),
+    ) {
+        let mut timer = Some(StageTimer::new(&self.counter.progress, Stage::Features));
+        let mut chunks = queries.chunks(chunk_size.max(1)).peekable();
+        while let Some(queries) = chunks.next() {
+            let chunk = timer
+                .as_mut()
+                .map(|timer| timer.time(|| compute(self, queries)));
+            if chunks.peek().is_none() || self.is_cancelled() {
+                if let Some(timer) = timer.take() {
+                    timer.finish();
+                }
+            }
+            match chunk {
+                Some(chunk) if !self.is_cancelled() => consume(chunk),
+                _ => break,
+            }
+        }
+    }
+}
+
+// Memory of the local features and per-point predictors of one point at one scale
+fn scale_bytes_per_point(predictors_dimension: usize, precision: Precision) -> usize {
+    let features = FEATURES_DIMENSION * precision.scalar_size()
+        + std::mem::size_of::<FeaturesRow>()
+        + std::mem::size_of::<NeighborhoodClass>();
+    let predictors = predictors_dimension * precision.scalar_size();
+    features + predictors
+}
+
+// Approximate memory needed per reference point while a chunk is processed: its local
+// features, its predictors_dimension per-point predictors and its rows of the two kNN graphs
+pub fn bytes_per_point(
+    neighborhood: &Neighborhood,
+    predictors_dimension: usize,
+    precision: Precision,
+) -> usize {
+    scale_bytes_per_point(predictors_dimension, precision)
+        + 2 * neighborhood.size() * std::mem::size_of::<usize>()
+}
+
+// Same as bytes_per_point for several kNN scales sharing the graphs of the largest one
+pub fn multiscale_bytes_per_point(
+    ks: &[usize],
+    predictors_dimension: usize,
+    precision: Precision,
+) -> usize {
+    let k_max = ks.iter().copied().max().unwrap_or(0);
+    ks.len() * scale_bytes_per_point(predictors_dimension, precision)
+        + 2 * k_max * std::mem::size_of::<usize>()
+}
+
+// Number of reference points processed at once so that a chunk fits in memory_budget bytes
+pub fn chunk_size_for_budget(
+    memory_budget: usize,
+    neighborhood: &Neighborhood,
+    predictors_dimension: usize,
+    precision: Precision,
+) -> usize {
+    (memory_budget / bytes_per_point(neighborhood, predictors_dimension, precision)).max(1)
+}
+
+pub fn multiscale_chunk_size_for_budget(
+    memory_budget: usize,
+    ks: &[usize],
+    predictors_dimension: usize,
+    precision: Precision,
+) -> usize {
+    (memory_budget / multiscale_bytes_per_point(ks, predictors_dimension, precision)).max(1)
+}
+
+// Reference points at which the features are computed, all of them unless sampled
+fn query_points<'p>(points_a: &'p [[f64; 3]], config: &PointPca2Config) -> Cow<'p, [[f64; 3]]> {
+    match config.sampling {
+        QuerySampling::All => Cow::Borrowed(points_a),
+        ref query_sampling => Cow::Owned(
+            sampling::sample_queries(points_a, query_sampling)
+                .into_iter()
+                .map(|i| points_a[i])
+                .collect(),
+        ),
+    }
+}
+
+// Local features of the (sampled) reference points and the classes of their neighborhoods,
+// in f64 whatever the precision of the config
+pub fn compute_features_with_config(
+    points_a: Vec<[f64; 3]>,
+    colors_a: Vec<[u8; 3]>,
+    points_b: Vec<[f64; 3]>,
+    colors_b: Vec<[u8; 3]>,
+    config: &PointPca2Config,
+) -> (DMatrix<f64>, Vec<NeighborhoodClass>) {
+    let queries = query_points(&points_a, config);
+    let kernel = Kernel::new(
+        &points_a,
+        &colors_a,
+        &points_b,
+        &colors_b,
+        config,
+        queries.len(),
+    );
+    let mut result = (DMatrix::zeros(0, FEATURES_DIMENSION), Vec::new());
+    kernel.run_chunks(
+        &queries,
+        queries.len(),
+        Kernel::compute_rows::<f64>,
+        |rows| result = rows,
+    );
+    result
+}
+
+// compute_features_with_config with a kNN neighborhood of search_size and the default options
 pub fn compute_features(
     points_a: Vec<[f64; 3]>,
     colors_a: Vec<[u8; 3]>,
@@ -13,56 +600,66 @@ pub fn compute_features(
     colors_b: Vec<[u8; 3]>,
     search_size: usize,
 ) -> DMatrix<f64> {
-    let kd_tree_a = knn_search::build_tree(&points_a);
-    let kd_tree_b = knn_search::build_tree(&points_b);
-    let nrows = points_a.len();
-    let mut local_features = DMatrix::zeros(nrows, FEATURES_DIMENSION);
-    local_features
-        .row_iter_mut()
-        .collect::<Vec<_>>()
-        .par_iter_mut()
-        .enumerate()
-        .for_each(|(i, row)| {
-            let knn_indices_a = knn_search::nearest_n(&kd_tree_a, &points_a[i], search_size);
-            let knn_indices_b = knn_search::nearest_n(&kd_tree_b, &points_a[i], search_size);
-            // Slice points and colors from their respective knn indices
-            let (sl_points_a, sl_colors_a) =
-                utils::slice_from_knn_indices(&points_a, &colors_a, &knn_indices_a, search_size);
-            let (sl_points_b, sl_colors_b) =
-                utils::slice_from_knn_indices(&points_b, &colors_b, &knn_indices_b, search_size);
-            // Principal components of reference data (new orthonormal basis)
-            let eigenvectors_a = pca::compute_pca(&sl_points_a);
-            // Project reference and distorted data onto the new orthonormal basis
-            let sl_points_a_mean = sl_points_a.row_mean();
-            let projection_a_to_a =
-                utils::subtract_row_from_matrix(&sl_points_a, &sl_points_a_mean) * &eigenvectors_a;
-            let projection_b_to_a =
-                utils::subtract_row_from_matrix(&sl_points_b, &sl_points_a_mean) * &eigenvectors_a;
-            // Mean values for projected geometric data and texture data
-            let mean_a = utils::concatenate_columns(&projection_a_to_a, &sl_colors_a).row_mean();
-            let mean_b = utils::concatenate_columns(&projection_b_to_a, &sl_colors_b).row_mean();
-            let proj_colors_a_concat = utils::concatenate_columns(&projection_a_to_a, &sl_colors_a);
-            let proj_colors_b_concat = utils::concatenate_columns(&projection_b_to_a, &sl_colors_b);
-            // Deviation from mean
-            let mean_deviation_a = utils::subtract_row_from_matrix(&proj_colors_a_concat, &mean_a);
-            let mean_deviation_b = utils::subtract_row_from_matrix(&proj_colors_b_concat, &mean_b);
-            // Variances and covariance
-            let variance_a = mean_deviation_a.map(|x| x.powi(2)).row_mean();
-            let variance_b = mean_deviation_b.map(|x| x.powi(2)).row_mean();
-            let covariance_ab = mean_deviation_a.component_mul(&mean_deviation_b).row_mean();
-            // Principal components of projected distorted data
-            let eigenvectors_b = pca::compute_pca(&projection_b_to_a).transpose();
-            // Update local features
-            row.columns_mut(0, 3).copy_from(&projection_a_to_a.row(0));
-            row.columns_mut(3, 3).copy_from(&projection_b_to_a.row(0));
-            row.columns_mut(6, 3).copy_from(&mean_a.columns(3, 3));
-            row.columns_mut(9, 6).copy_from(&mean_b);
-            row.columns_mut(15, 6).copy_from(&variance_a);
-            row.columns_mut(21, 6).copy_from(&variance_b);
-            row.columns_mut(27, 6).copy_from(&covariance_ab);
-            row.columns_mut(33, 3).copy_from(&eigenvectors_b.row(0));
-            row.columns_mut(36, 3).copy_from(&eigenvectors_b.row(1));
-            row.columns_mut(39, 3).copy_from(&eigenvectors_b.row(2));
-        });
-    local_features
+    let config = PointPca2Config::new(search_size);
+    compute_features_with_config(points_a, colors_a, points_b, colors_b, &config).0
+}
+
+// Computes the local features of chunk_size (sampled) reference points at a time, in order,
+// handing each chunk and the classes of its points to consume before computing the next one.
+// Stops without consuming the chunk in progress once the run is cancelled. The features are
+// computed in the precision T, the precision of the config being ignored
+pub fn compute_features_chunked<T: Real>(
+    points_a: &[[f64; 3]],
+    colors_a: &[[u8; 3]],
+    points_b: &[[f64; 3]],
+    colors_b: &[[u8; 3]],
+    config: &PointPca2Config,
+    chunk_size: usize,
+    mut consume: impl FnMut(DMatrix<T>, Vec<NeighborhoodClass>),
+) {
+    let queries = query_points(points_a, config);
+    let kernel = Kernel::new(
+        points_a,
+        colors_a,
+        points_b,
+        colors_b,
+        config,
+        queries.len(),
+    );
+    kernel.run_chunks(
+        &queries,
+        chunk_size,
+        Kernel::compute_rows,
+        |(rows, classes)| consume(rows, classes),
+    );
+}
+
+// Computes the local features of the reference points for every kNN size of ks, handing
+// consume the features and classes of each scale in the order of ks. The points are taken
+// in chunks fitting the memory budget of the config, if any, and cancelled like in
+// compute_features_chunked. The neighborhood and precision of the config are ignored, each
+// scale is computed as if configured as Knn(k)
+pub fn compute_features_multiscale<T: Real>(
+    points_a: &[[f64; 3]],
+    colors_a: &[[u8; 3]],
+    points_b: &[[f64; 3]],
+    colors_b: &[[u8; 3]],
+    config: &PointPca2Config,
+    ks: &[usize],
+    consume: impl FnMut(Vec<(DMatrix<T>, Vec<NeighborhoodClass>)>),
+) {
+    let queries = query_points(points_a, config);
+    let total_points = queries.len() * ks.len();
+    let kernel = Kernel::new(points_a, colors_a, points_b, colors_b, config, total_points);
+    let chunk_size = match config.memory_budget {
+        Some(memory_budget) => multiscale_chunk_size_for_budget(
+            memory_budget,
+            ks,
+            config.predictors.dimension(),
+            T::PRECISION,
+        ),
+        None => queries.len(),
+    };
+    let compute = |kernel: &Kernel, queries: &[[f64; 3]]| kernel.compute_knn_rows(queries, ks);
+    kernel.run_chunks(&queries, chunk_size, compute, consume);
 }
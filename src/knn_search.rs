@@ -1,19 +1,318 @@
+use crate::neighbor_index::{self, IndexBackend, NeighborIndex};
+use crate::utils;
 use kd_tree;
+use rayon::prelude::*;
 
-pub fn build_tree<'a>(points: &'a Vec<[f64; 3]>) -> kd_tree::KdIndexTree3<'a, [f64; 3]> {
-    let kdtree = kd_tree::KdIndexTree3::par_build_by_ordered_float(points);
-    kdtree
+// Fills the unused slots of rows that have fewer than k neighbors
+pub const NO_NEIGHBOR: usize = usize::MAX;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Neighborhood {
+    // Fixed number of nearest neighbors
+    Knn(usize),
+    // All neighbors within the radius, or the single nearest one if none is found
+    Radius(f64),
+    // The k nearest neighbors within the radius, keeping at least min_count nearest ones
+    Hybrid {
+        k: usize,
+        radius: f64,
+        min_count: usize,
+    },
+}
+
+impl Neighborhood {
+    // Number of neighbors each query is expected to return
+    pub fn size(&self) -> usize {
+        match *self {
+            Neighborhood::Knn(k) => k,
+            Neighborhood::Radius(_) => 1,
+            Neighborhood::Hybrid { min_count, .. } => min_count,
+        }
+    }
+
+    pub fn clamped(&self, npoints: usize) -> Self {
+        match *self {
+            Neighborhood::Knn(k) => Neighborhood::Knn(k.min(npoints)),
+            Neighborhood::Radius(radius) => Neighborhood::Radius(radius),
+            Neighborhood::Hybrid {
+                k,
+                radius,
+                min_count,
+            } => Neighborhood::Hybrid {
+                k: k.min(npoints),
+                radius,
+                min_count: min_count.min(npoints),
+            },
+        }
+    }
+}
+
+// Behavior when a cloud has fewer points than the neighborhood size
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SmallCloudPolicy {
+    // Fail with PointPca2Error::CloudSmallerThanNeighborhood
+    Error,
+    // Reduce the neighborhood size to the number of points of the smaller cloud
+    #[default]
+    Clamp,
+    // Keep the neighborhood size, repeating the found neighbors to fill it
+    Pad,
+}
+
+// Repeats the neighbors cyclically until size entries are filled
+pub fn pad_neighbors(indices: &[usize], size: usize) -> Vec<usize> {
+    indices.iter().copied().cycle().take(size).collect()
+}
+
+pub fn build_tree(points: &[[f64; 3]]) -> kd_tree::KdIndexTree3<'_, [f64; 3]> {
+    kd_tree::KdIndexTree3::par_build_by_ordered_float(points)
 }
 
-pub fn nearest_n<'a>(
-    kdtree: &'a kd_tree::KdIndexTree3<[f64; 3]>,
+pub fn nearest_n(index: &dyn NeighborIndex, point: &[f64; 3], n: usize) -> Vec<usize> {
+    let neighbors = index.knn(point, n);
+    neighbors
+        .iter()
+        .map(|&(idx, _)| idx)
+        .collect::<Vec<usize>>()
+}
+
+pub fn nearest_n_with_distances(
+    index: &dyn NeighborIndex,
     point: &[f64; 3],
     n: usize,
-) -> Vec<usize> {
-    let neighbors = kdtree.nearests(point, n);
-    let indices = neighbors
+) -> Vec<(usize, f64)> {
+    let neighbors = index.knn(point, n);
+    neighbors
+        .iter()
+        .map(|&(idx, squared_distance)| (idx, squared_distance.sqrt()))
+        .collect::<Vec<(usize, f64)>>()
+}
+
+// Neighbors strictly closer than radius, sorted by increasing distance
+pub fn within_radius(index: &dyn NeighborIndex, point: &[f64; 3], radius: f64) -> Vec<usize> {
+    let neighbors = index.radius(point, radius);
+    neighbors
         .iter()
-        .map(|nbr| *nbr.item)
-        .collect::<Vec<usize>>();
-    indices
+        .map(|&(idx, _)| idx)
+        .collect::<Vec<usize>>()
+}
+
+pub fn neighborhood(
+    index: &dyn NeighborIndex,
+    point: &[f64; 3],
+    neighborhood: &Neighborhood,
+) -> Vec<usize> {
+    match *neighborhood {
+        Neighborhood::Knn(k) => nearest_n(index, point, k),
+        Neighborhood::Radius(radius) => {
+            let indices = within_radius(index, point, radius);
+            if indices.is_empty() {
+                nearest_n(index, point, 1)
+            } else {
+                indices
+            }
+        }
+        Neighborhood::Hybrid {
+            k,
+            radius,
+            min_count,
+        } => {
+            let neighbors = index.knn(point, k.max(min_count));
+            neighbors
+                .iter()
+                .enumerate()
+                .take_while(|&(i, &(_, dist))| i < min_count || (i < k && dist < radius * radius))
+                .map(|(_, &(idx, _))| idx)
+                .collect::<Vec<usize>>()
+        }
+    }
+}
+
+// Queries the k nearest neighbors of every query point in parallel, writing row i of
+// the row-major queries.len() x k buffers with the neighbors of queries[i]
+pub fn nearest_n_into(
+    index: &dyn NeighborIndex,
+    queries: &[[f64; 3]],
+    k: usize,
+    indices: &mut [usize],
+    squared_distances: Option<&mut [f64]>,
+) {
+    assert_eq!(
+        indices.len(),
+        queries.len() * k,
+        "indices must hold k entries per query."
+    );
+    if k == 0 {
+        return;
+    }
+    match squared_distances {
+        Some(squared_distances) => {
+            assert_eq!(
+                squared_distances.len(),
+                queries.len() * k,
+                "squared_distances must hold k entries per query."
+            );
+            indices
+                .par_chunks_mut(k)
+                .zip(squared_distances.par_chunks_mut(k))
+                .zip(queries.par_iter())
+                .for_each(|((indices_row, distances_row), query)| {
+                    index.knn_into(query, indices_row, Some(distances_row))
+                });
+        }
+        None => {
+            // Distances are still needed during the search, in a row reused across queries
+            indices
+                .par_chunks_mut(k)
+                .zip(queries.par_iter())
+                .for_each_init(
+                    || vec![0.; k],
+                    |distances_row, (indices_row, query)| {
+                        index.knn_into(query, indices_row, Some(distances_row))
+                    },
+                );
+        }
+    }
+}
+
+// Flat N x k kNN graph whose buffers can be refilled for new queries without reallocating
+#[derive(Clone, Debug)]
+pub struct KnnGraph {
+    k: usize,
+    indices: Vec<usize>,
+    squared_distances: Option<Vec<f64>>,
+}
+
+impl KnnGraph {
+    pub fn new(capacity: usize, k: usize, with_distances: bool) -> Self {
+        Self {
+            k,
+            indices: Vec::with_capacity(capacity * k),
+            squared_distances: with_distances.then(|| Vec::with_capacity(capacity * k)),
+        }
+    }
+
+    pub fn build(
+        index: &dyn NeighborIndex,
+        queries: &[[f64; 3]],
+        k: usize,
+        with_distances: bool,
+    ) -> Self {
+        let mut graph = Self::new(queries.len(), k, with_distances);
+        graph.fill(index, queries);
+        graph
+    }
+
+    pub fn fill(&mut self, index: &dyn NeighborIndex, queries: &[[f64; 3]]) {
+        let len = queries.len() * self.k;
+        self.indices.resize(len, NO_NEIGHBOR);
+        if let Some(squared_distances) = self.squared_distances.as_mut() {
+            squared_distances.resize(len, f64::INFINITY);
+        }
+        nearest_n_into(
+            index,
+            queries,
+            self.k,
+            &mut self.indices,
+            self.squared_distances.as_deref_mut(),
+        );
+    }
+
+    pub fn k(&self) -> usize {
+        self.k
+    }
+
+    pub fn len(&self) -> usize {
+        self.indices.len().checked_div(self.k).unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn indices(&self) -> &[usize] {
+        &self.indices
+    }
+
+    pub fn squared_distances(&self) -> Option<&[f64]> {
+        self.squared_distances.as_deref()
+    }
+
+    // Neighbors of query i, without the NO_NEIGHBOR padding
+    pub fn neighbors(&self, i: usize) -> &[usize] {
+        let row = &self.indices[i * self.k..(i + 1) * self.k];
+        let count = row.partition_point(|&idx| idx != NO_NEIGHBOR);
+        &row[..count]
+    }
+
+    pub fn neighbor_distances(&self, i: usize) -> Option<&[f64]> {
+        let count = self.neighbors(i).len();
+        self.squared_distances
+            .as_ref()
+            .map(|squared_distances| &squared_distances[i * self.k..i * self.k + count])
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RecallReport {
+    pub sample_size: usize,
+    pub k: usize,
+    // Fraction of returned neighbors that are as close as the exact k-th nearest neighbor
+    pub recall: f64,
+    // Mean ratio between the returned and exact k-th nearest neighbor distances
+    pub mean_kth_distance_ratio: f64,
+}
+
+// Measures the recall of a backend against the exact kd-tree search, querying
+// sample_size randomly chosen points of queries against points
+pub fn measure_recall(
+    points: &[[f64; 3]],
+    queries: &[[f64; 3]],
+    backend: &IndexBackend,
+    k: usize,
+    sample_size: usize,
+    seed: u64,
+) -> RecallReport {
+    let exact_index = neighbor_index::build_index(points, &IndexBackend::KdTree);
+    let index = neighbor_index::build_index(points, backend);
+    let sample = utils::sample_indices(queries.len(), sample_size, seed);
+    let (matches, total, ratio_sum) = sample
+        .par_iter()
+        .map(|&i| {
+            let exact = exact_index.knn(&queries[i], k);
+            let approximate = index.knn(&queries[i], k);
+            let (Some(exact_kth), Some(approximate_kth)) = (exact.last(), approximate.last())
+            else {
+                return (0, 0, 1.);
+            };
+            // Distance based, so equidistant neighbors returned in another order still match
+            let matches = approximate
+                .iter()
+                .filter(|&&(_, dist)| dist <= exact_kth.1)
+                .count();
+            let ratio = if exact_kth.1 > 0. {
+                (approximate_kth.1 / exact_kth.1).sqrt()
+            } else {
+                1.
+            };
+            (matches, exact.len(), ratio)
+        })
+        .collect::<Vec<(usize, usize, f64)>>()
+        .into_iter()
+        // Summed sequentially so the report does not depend on the thread count
+        .fold((0, 0, 0.), |a, b| (a.0 + b.0, a.1 + b.1, a.2 + b.2));
+    RecallReport {
+        sample_size: sample.len(),
+        k,
+        recall: if total > 0 {
+            matches as f64 / total as f64
+        } else {
+            1.
+        },
+        mean_kth_distance_ratio: if sample.is_empty() {
+            1.
+        } else {
+            ratio_sum / sample.len() as f64
+        },
+    }
 }
@@ -5,17 +5,595 @@ extern crate ordered_float;
 extern crate ply_rs;
 extern crate rayon;
 
+pub mod config;
+pub mod error;
+pub mod execution;
 pub mod features;
 pub mod knn_search;
+pub mod local_geometry;
+pub mod neighbor_index;
 pub mod pca;
 pub mod ply_manager;
 pub mod pooling;
+pub mod precision;
 pub mod predictors;
 pub mod preprocessing;
+pub mod progress;
+pub mod reduced_reference;
+pub mod sampling;
 pub mod spatial_metrics;
 pub mod utils;
 
-pub fn compute_pointpca2<'a>(
+pub use config::PointPca2Config;
+pub use error::PointPca2Error;
+use knn_search::SmallCloudPolicy;
+use pca::{DegeneratePolicy, NeighborhoodClass, NeighborhoodClassCounts};
+use pooling::Accumulator;
+use precision::{Precision, Real};
+use progress::{ProgressSink, Stage, StageTimer};
+use std::sync::Arc;
+
+pub struct PointPca2Result {
+    pub predictors: na::Matrix1xX<f64>,
+    // Column names of the predictors, as registered in the config
+    pub predictor_names: Vec<String>,
+    // Number of points dropped by the outlier filters of the reference (a) and distorted (b) clouds
+    pub outliers_removed_a: usize,
+    pub outliers_removed_b: usize,
+    // Preprocessing statistics, only available when `preprocess_report` is enabled
+    pub preprocess_report_a: Option<preprocessing::PreprocessReport>,
+    pub preprocess_report_b: Option<preprocessing::PreprocessReport>,
+    // Policy applied because a cloud had fewer points than the neighborhood size, if any
+    pub small_cloud_policy: Option<SmallCloudPolicy>,
+    // Number of points per neighborhood class, after applying the degenerate policy
+    pub neighborhood_classes: NeighborhoodClassCounts,
+    // Bootstrap intervals of the predictors, only available when `bootstrap` is set
+    pub confidence_intervals: Option<sampling::ConfidenceIntervals>,
+}
+
+pub struct MultiScaleResult {
+    // Neighborhood sizes, in the order their predictors are concatenated
+    pub scales: Vec<usize>,
+    // Registered predictors of every scale, concatenated
+    pub predictors: na::Matrix1xX<f64>,
+    // Label of each predictor, "k<scale>_<column name>"
+    pub labels: Vec<String>,
+    pub outliers_removed_a: usize,
+    pub outliers_removed_b: usize,
+    pub preprocess_report_a: Option<preprocessing::PreprocessReport>,
+    pub preprocess_report_b: Option<preprocessing::PreprocessReport>,
+    // Policy applied because a cloud had fewer points than the largest scale, if any
+    pub small_cloud_policy: Option<SmallCloudPolicy>,
+    // Number of points per neighborhood class at each scale
+    pub neighborhood_classes: Vec<NeighborhoodClassCounts>,
+    // Bootstrap intervals of the concatenated predictors
+    pub confidence_intervals: Option<sampling::ConfidenceIntervals>,
+}
+
+struct PreprocessedCloud {
+    points: Vec<[f64; 3]>,
+    colors: Vec<[u8; 3]>,
+    outliers_removed: usize,
+    report: Option<preprocessing::PreprocessReport>,
+}
+
+fn preprocess(
+    points: Vec<[f64; 3]>,
+    colors: Vec<[u8; 3]>,
+    outlier_filter: &Option<preprocessing::OutlierFilter>,
+    with_report: bool,
+) -> PreprocessedCloud {
+    if with_report {
+        let (points, colors, report) = preprocessing::preprocess_point_cloud_with_report(
+            points,
+            colors,
+            outlier_filter.as_ref(),
+        );
+        return PreprocessedCloud {
+            points,
+            colors,
+            outliers_removed: report.outliers_removed,
+            report: Some(report),
+        };
+    }
+    let (points, colors) = preprocessing::preprocess_point_cloud(points, colors);
+    let (points, colors, outliers_removed) = match outlier_filter {
+        Some(filter) => preprocessing::remove_outliers(points, colors, filter),
+        None => (points, colors, 0),
+    };
+    PreprocessedCloud {
+        points,
+        colors,
+        outliers_removed,
+        report: None,
+    }
+}
+
+fn preprocess_clouds(
+    points_a: Vec<[f64; 3]>,
+    colors_a: Vec<[u8; 3]>,
+    points_b: Vec<[f64; 3]>,
+    colors_b: Vec<[u8; 3]>,
+    config: &PointPca2Config,
+) -> (PreprocessedCloud, PreprocessedCloud) {
+    let mut timer = StageTimer::new(&config.progress, Stage::Preprocess);
+    let (cloud_a, cloud_b) = timer.time(|| {
+        let cloud_a = preprocess(
+            points_a,
+            colors_a,
+            &config.outlier_filter_a,
+            config.preprocess_report,
+        );
+        let cloud_b = preprocess(
+            points_b,
+            colors_b,
+            &config.outlier_filter_b,
+            config.preprocess_report,
+        );
+        (cloud_a, cloud_b)
+    });
+    timer.finish();
+    if config.outlier_filter_a.is_some() || config.outlier_filter_b.is_some() {
+        let message = format!(
+            "Removed outliers: {} (reference), {} (distorted)",
+            cloud_a.outliers_removed, cloud_b.outliers_removed
+        );
+        config.progress.message(&message);
+    }
+    (cloud_a, cloud_b)
+}
+
+// Adjusts the neighborhood of the feature stage to clouds smaller than it
+fn resolve_small_clouds(
+    config: &PointPca2Config,
+    npoints_a: usize,
+    npoints_b: usize,
+) -> Result<(PointPca2Config, Option<SmallCloudPolicy>), PointPca2Error> {
+    for (cloud, npoints) in [("reference", npoints_a), ("distorted", npoints_b)] {
+        if npoints == 0 {
+            return Err(PointPca2Error::EmptyCloud { cloud });
+        }
+    }
+    let neighborhood_size = config.neighborhood.size();
+    let smallest = if npoints_a <= npoints_b {
+        ("reference", npoints_a)
+    } else {
+        ("distorted", npoints_b)
+    };
+    if smallest.1 >= neighborhood_size {
+        return Ok((config.clone(), None));
+    }
+    let mut resolved = config.clone();
+    match config.small_cloud_policy {
+        SmallCloudPolicy::Error => {
+            return Err(PointPca2Error::CloudSmallerThanNeighborhood {
+                cloud: smallest.0,
+                points: smallest.1,
+                neighborhood_size,
+            })
+        }
+        SmallCloudPolicy::Clamp => resolved.neighborhood = config.neighborhood.clamped(smallest.1),
+        SmallCloudPolicy::Pad => {}
+    }
+    Ok((resolved, Some(config.small_cloud_policy)))
+}
+
+// Rejects the neighborhoods and query sampling that would select no point
+fn validate_config(
+    config: &PointPca2Config,
+    neighborhoods: &[knn_search::Neighborhood],
+) -> Result<(), PointPca2Error> {
+    let invalid = |message: &str| {
+        Err(PointPca2Error::InvalidConfig {
+            message: message.to_string(),
+        })
+    };
+    for neighborhood in neighborhoods {
+        match *neighborhood {
+            knn_search::Neighborhood::Knn(0) | knn_search::Neighborhood::Hybrid { k: 0, .. } => {
+                return invalid("the neighborhood size must be positive.")
+            }
+            knn_search::Neighborhood::Radius(radius)
+            | knn_search::Neighborhood::Hybrid { radius, .. }
+                if radius.is_nan() || radius <= 0. =>
+            {
+                return invalid("the neighborhood radius must be positive.")
+            }
+            _ => {}
+        }
+    }
+    match config.sampling {
+        sampling::QuerySampling::Uniform { count: 0, .. }
+        | sampling::QuerySampling::FarthestPoint { count: 0 } => {
+            invalid("the number of sampled points must be positive.")
+        }
+        sampling::QuerySampling::Voxel { size } if !size.is_finite() || size <= 0. => {
+            invalid("the sampling voxel size must be positive and finite.")
+        }
+        _ => Ok(()),
+    }
+}
+
+// Drops the local features of the neighborhoods skipped by the degenerate policy
+fn pooled_rows<T: Real>(
+    local_features: na::DMatrix<T>,
+    classes: &[NeighborhoodClass],
+    policy: &DegeneratePolicy,
+) -> na::DMatrix<T> {
+    let DegeneratePolicy::Skip { from } = *policy else {
+        return local_features;
+    };
+    let kept = (0..classes.len())
+        .filter(|&i| classes[i] < from)
+        .collect::<Vec<_>>();
+    if kept.len() == classes.len() {
+        return local_features;
+    }
+    local_features.select_rows(&kept)
+}
+
+// Mean pooling of per-point predictors, which are also kept for the bootstrap if requested
+struct PredictorPool {
+    accumulator: pooling::MeanAccumulator,
+    kept: Option<Vec<na::DMatrix<f64>>>,
+}
+
+impl PredictorPool {
+    fn new(dimension: usize, keep: bool) -> Self {
+        Self {
+            accumulator: pooling::MeanAccumulator::new(dimension),
+            kept: keep.then(Vec::new),
+        }
+    }
+
+    fn push<T: Real>(&mut self, point_predictors: na::DMatrix<T>) {
+        self.accumulator.push_real_rows(&point_predictors);
+        if let Some(kept) = self.kept.as_mut() {
+            kept.push(point_predictors.map(T::to_f64));
+        }
+    }
+
+    fn count(&self) -> usize {
+        self.accumulator.count()
+    }
+
+    fn finish(
+        self,
+        bootstrap: &Option<sampling::Bootstrap>,
+    ) -> (na::Matrix1xX<f64>, Option<sampling::ConfidenceIntervals>) {
+        let intervals = bootstrap.as_ref().zip(self.kept).map(|(bootstrap, kept)| {
+            sampling::bootstrap_means(&utils::concatenate_rows(&kept), bootstrap)
+        });
+        (self.accumulator.finish(), intervals)
+    }
+}
+
+// Pools the per-point predictors of chunks of chunk_size reference points as they are
+// computed, so that the local features of the whole cloud are never held at once
+fn compute_pooled_predictors<T: Real>(
+    cloud_a: &PreprocessedCloud,
+    cloud_b: &PreprocessedCloud,
+    config: &PointPca2Config,
+    chunk_size: usize,
+    timer: &mut StageTimer,
+) -> Result<(PredictorPool, NeighborhoodClassCounts), PointPca2Error> {
+    let mut pool = PredictorPool::new(config.predictors.dimension(), config.bootstrap.is_some());
+    let mut neighborhood_classes = NeighborhoodClassCounts::default();
+    let mut pooled = Ok(());
+    features::compute_features_chunked::<T>(
+        &cloud_a.points,
+        &cloud_a.colors,
+        &cloud_b.points,
+        &cloud_b.colors,
+        config,
+        chunk_size,
+        |local_features, classes| {
+            // Chunks after a failing one are not pooled
+            if pooled.is_err() {
+                return;
+            }
+            neighborhood_classes.merge(&NeighborhoodClassCounts::from_classes(&classes));
+            let local_features = pooled_rows(local_features, &classes, &config.degenerate_policy);
+            let registry = &config.predictors;
+            pooled = timer.time(|| {
+                let point_predictors = registry.compute_point_predictors(&local_features)?;
+                pool.push(point_predictors);
+                Ok(())
+            });
+        },
+    );
+    pooled.map(|_| (pool, neighborhood_classes))
+}
+
+// Same as compute_pooled_predictors at every kNN size of ks, with one pool per scale
+fn compute_multiscale_pooled_predictors<T: Real>(
+    cloud_a: &PreprocessedCloud,
+    cloud_b: &PreprocessedCloud,
+    config: &PointPca2Config,
+    ks: &[usize],
+    timer: &mut StageTimer,
+) -> Result<(Vec<PredictorPool>, Vec<NeighborhoodClassCounts>), PointPca2Error> {
+    let mut pools = ks
+        .iter()
+        .map(|_| PredictorPool::new(config.predictors.dimension(), config.bootstrap.is_some()))
+        .collect::<Vec<_>>();
+    let mut neighborhood_classes = vec![NeighborhoodClassCounts::default(); ks.len()];
+    let mut pooled = Ok(());
+    features::compute_features_multiscale::<T>(
+        &cloud_a.points,
+        &cloud_a.colors,
+        &cloud_b.points,
+        &cloud_b.colors,
+        config,
+        ks,
+        |chunk| {
+            for (s, (local_features, classes)) in chunk.into_iter().enumerate() {
+                if pooled.is_err() {
+                    return;
+                }
+                neighborhood_classes[s].merge(&NeighborhoodClassCounts::from_classes(&classes));
+                let local_features =
+                    pooled_rows(local_features, &classes, &config.degenerate_policy);
+                let registry = &config.predictors;
+                pooled = timer.time(|| {
+                    let point_predictors = registry.compute_point_predictors(&local_features)?;
+                    pools[s].push(point_predictors);
+                    Ok(())
+                });
+            }
+        },
+    );
+    pooled.map(|_| (pools, neighborhood_classes))
+}
+
+// Config restricted to the predictors of the selected attributes
+fn attributes_config(config: &PointPca2Config) -> Result<PointPca2Config, PointPca2Error> {
+    let predictors = config.predictors.for_attributes(config.attributes);
+    if predictors.dimension() == 0 {
+        return Err(PointPca2Error::NoPredictors);
+    }
+    Ok(PointPca2Config {
+        predictors,
+        ..config.clone()
+    })
+}
+
+// Colors are not read when only the geometry is compared, so clouds without colors get
+// placeholder ones for preprocessing
+fn attributes_colors(
+    points: &[[f64; 3]],
+    colors: Vec<[u8; 3]>,
+    config: &PointPca2Config,
+) -> Vec<[u8; 3]> {
+    if config.attributes.includes_texture() || colors.len() == points.len() {
+        colors
+    } else {
+        vec![[0; 3]; points.len()]
+    }
+}
+
+fn pointpca2(
+    points_a: Vec<[f64; 3]>,
+    colors_a: Vec<[u8; 3]>,
+    points_b: Vec<[f64; 3]>,
+    colors_b: Vec<[u8; 3]>,
+    config: &PointPca2Config,
+) -> Result<PointPca2Result, PointPca2Error> {
+    validate_config(config, &[config.neighborhood])?;
+    let config = &attributes_config(config)?;
+    let colors_a = attributes_colors(&points_a, colors_a, config);
+    let colors_b = attributes_colors(&points_b, colors_b, config);
+    let (cloud_a, cloud_b) = preprocess_clouds(points_a, colors_a, points_b, colors_b, config);
+    execution::check_cancelled(&config.cancellation)?;
+    let (features_config, small_cloud_policy) =
+        resolve_small_clouds(config, cloud_a.points.len(), cloud_b.points.len())?;
+    if let Some(policy) = small_cloud_policy {
+        let message = format!(
+            "A cloud is smaller than the neighborhood size, applying {:?}",
+            policy
+        );
+        config.progress.message(&message);
+    }
+    let npoints = cloud_a.points.len();
+    let chunk_size = match config.memory_budget {
+        Some(memory_budget) => {
+            let chunk_size = features::chunk_size_for_budget(
+                memory_budget,
+                &features_config.neighborhood,
+                config.predictors.dimension(),
+                config.precision,
+            );
+            let message = format!(
+                "Computing local features and predictors in chunks of {} points",
+                chunk_size
+            );
+            config.progress.message(&message);
+            chunk_size
+        }
+        None => npoints,
+    };
+    let mut timer = StageTimer::new(&config.progress, Stage::Predictors);
+    let (pool, neighborhood_classes) = match config.precision {
+        Precision::F64 => compute_pooled_predictors::<f64>(
+            &cloud_a,
+            &cloud_b,
+            &features_config,
+            chunk_size,
+            &mut timer,
+        ),
+        Precision::F32 => compute_pooled_predictors::<f32>(
+            &cloud_a,
+            &cloud_b,
+            &features_config,
+            chunk_size,
+            &mut timer,
+        ),
+    }?;
+    execution::check_cancelled(&config.cancellation)?;
+    if pool.count() == 0 {
+        return Err(PointPca2Error::AllNeighborhoodsSkipped { points: npoints });
+    }
+    if config.sampling != sampling::QuerySampling::All {
+        let message = format!("Pooled {} sampled points out of {}", pool.count(), npoints);
+        config.progress.message(&message);
+    }
+    let (predictors, confidence_intervals) = timer.time(|| pool.finish(&config.bootstrap));
+    timer.finish();
+    Ok(PointPca2Result {
+        predictors,
+        predictor_names: config.predictors.column_names(),
+        outliers_removed_a: cloud_a.outliers_removed,
+        outliers_removed_b: cloud_b.outliers_removed,
+        preprocess_report_a: cloud_a.report,
+        preprocess_report_b: cloud_b.report,
+        small_cloud_policy,
+        neighborhood_classes,
+        confidence_intervals,
+    })
+}
+
+fn pointpca2_multiscale(
+    points_a: Vec<[f64; 3]>,
+    colors_a: Vec<[u8; 3]>,
+    points_b: Vec<[f64; 3]>,
+    colors_b: Vec<[u8; 3]>,
+    scales: &[usize],
+    config: &PointPca2Config,
+) -> Result<MultiScaleResult, PointPca2Error> {
+    if scales.is_empty() {
+        return Err(PointPca2Error::NoScales);
+    }
+    let neighborhoods = scales
+        .iter()
+        .map(|&scale| knn_search::Neighborhood::Knn(scale))
+        .collect::<Vec<_>>();
+    validate_config(config, &neighborhoods)?;
+    let config = &attributes_config(config)?;
+    let colors_a = attributes_colors(&points_a, colors_a, config);
+    let colors_b = attributes_colors(&points_b, colors_b, config);
+    let (cloud_a, cloud_b) = preprocess_clouds(points_a, colors_a, points_b, colors_b, config);
+    execution::check_cancelled(&config.cancellation)?;
+    let mut ks = Vec::with_capacity(scales.len());
+    let mut small_cloud_policy = None;
+    for &scale in scales {
+        let scale_config = PointPca2Config {
+            neighborhood: knn_search::Neighborhood::Knn(scale),
+            ..config.clone()
+        };
+        let (resolved, policy) =
+            resolve_small_clouds(&scale_config, cloud_a.points.len(), cloud_b.points.len())?;
+        ks.push(resolved.neighborhood.size());
+        small_cloud_policy = small_cloud_policy.or(policy);
+    }
+    if let Some(policy) = small_cloud_policy {
+        let message = format!(
+            "A cloud is smaller than the largest neighborhood size, applying {:?}",
+            policy
+        );
+        config.progress.message(&message);
+    }
+    let message = format!("Computing local features and predictors at scales {:?}", ks);
+    config.progress.message(&message);
+    let mut timer = StageTimer::new(&config.progress, Stage::Predictors);
+    let (pools, neighborhood_classes) = match config.precision {
+        Precision::F64 => {
+            compute_multiscale_pooled_predictors::<f64>(&cloud_a, &cloud_b, config, &ks, &mut timer)
+        }
+        Precision::F32 => {
+            compute_multiscale_pooled_predictors::<f32>(&cloud_a, &cloud_b, config, &ks, &mut timer)
+        }
+    }?;
+    execution::check_cancelled(&config.cancellation)?;
+    if pools.iter().any(|pool| pool.count() == 0) {
+        return Err(PointPca2Error::AllNeighborhoodsSkipped {
+            points: cloud_a.points.len(),
+        });
+    }
+    let (predictors, intervals): (Vec<_>, Vec<_>) = timer.time(|| {
+        pools
+            .into_iter()
+            .map(|pool| pool.finish(&config.bootstrap))
+            .unzip()
+    });
+    timer.finish();
+    let concatenate = |rows: Vec<na::Matrix1xX<f64>>| {
+        na::Matrix1xX::from_iterator(
+            rows.iter().map(|row| row.len()).sum(),
+            rows.iter().flat_map(|row| row.iter().copied()),
+        )
+    };
+    let confidence_intervals = intervals
+        .into_iter()
+        .collect::<Option<Vec<_>>>()
+        .map(|intervals| {
+            let (lower, upper) = intervals
+                .into_iter()
+                .map(|interval| (interval.lower, interval.upper))
+                .unzip();
+            sampling::ConfidenceIntervals {
+                lower: concatenate(lower),
+                upper: concatenate(upper),
+            }
+        });
+    let column_names = config.predictors.column_names();
+    let labels = scales
+        .iter()
+        .flat_map(|scale| {
+            column_names
+                .iter()
+                .map(move |name| format!("k{}_{}", scale, name))
+        })
+        .collect();
+    Ok(MultiScaleResult {
+        scales: scales.to_vec(),
+        predictors: concatenate(predictors),
+        labels,
+        outliers_removed_a: cloud_a.outliers_removed,
+        outliers_removed_b: cloud_b.outliers_removed,
+        preprocess_report_a: cloud_a.report,
+        preprocess_report_b: cloud_b.report,
+        small_cloud_policy,
+        neighborhood_classes,
+        confidence_intervals,
+    })
+}
+
+pub fn compute_pointpca2_with_config(
+    points_a: Vec<[f64; 3]>,
+    colors_a: Vec<[u8; 3]>,
+    points_b: Vec<[f64; 3]>,
+    colors_b: Vec<[u8; 3]>,
+    config: &PointPca2Config,
+) -> Result<PointPca2Result, PointPca2Error> {
+    execution::check_cancelled(&config.cancellation)?;
+    config
+        .threads
+        .install(|| pointpca2(points_a, colors_a, points_b, colors_b, config))?
+}
+
+// PointPCA2 at several kNN neighborhood sizes, with a single kNN query per point at the
+// largest size serving all of them. The predictors of each scale are identical to those of
+// compute_pointpca2_with_config with that neighborhood size. The neighborhood of the config
+// is ignored
+pub fn compute_pointpca2_multiscale(
+    points_a: Vec<[f64; 3]>,
+    colors_a: Vec<[u8; 3]>,
+    points_b: Vec<[f64; 3]>,
+    colors_b: Vec<[u8; 3]>,
+    scales: &[usize],
+    config: &PointPca2Config,
+) -> Result<MultiScaleResult, PointPca2Error> {
+    execution::check_cancelled(&config.cancellation)?;
+    config
+        .threads
+        .install(|| pointpca2_multiscale(points_a, colors_a, points_b, colors_b, scales, config))?
+}
+
+// compute_pointpca2_with_config with a kNN neighborhood of search_size and the default
+// options. Panics where it would return an error, which is when search_size is 0 or a cloud
+// is empty, before or after preprocessing. Clouds smaller than search_size are handled by
+// SmallCloudPolicy::Clamp
+pub fn compute_pointpca2(
     points_a: Vec<[f64; 3]>,
     colors_a: Vec<[u8; 3]>,
     points_b: Vec<[f64; 3]>,
@@ -23,13 +601,16 @@ pub fn compute_pointpca2<'a>(
     search_size: usize,
     verbose: bool,
 ) -> na::Matrix1xX<f64> {
-    utils::print_if_verbose("Preprocessing", &verbose);
-    let (points_a, colors_a) = preprocessing::preprocess_point_cloud(points_a, colors_a);
-    let (points_b, colors_b) = preprocessing::preprocess_point_cloud(points_b, colors_b);
-    utils::print_if_verbose("Computing local features", &verbose);
-    let local_features =
-        features::compute_features(points_a, colors_a, points_b, colors_b, search_size);
-    utils::print_if_verbose("Computing predictors", &verbose);
-    let predictors_result = predictors::compute_predictors(local_features);
-    predictors_result
+    let progress: Arc<dyn ProgressSink> = if verbose {
+        Arc::new(progress::StderrProgress::new())
+    } else {
+        Arc::new(progress::NoProgress)
+    };
+    let config = PointPca2Config {
+        progress,
+        ..PointPca2Config::new(search_size)
+    };
+    compute_pointpca2_with_config(points_a, colors_a, points_b, colors_b, &config)
+        .expect("Failure when computing PointPCA2.")
+        .predictors
 }
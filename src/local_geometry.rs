@@ -0,0 +1,206 @@
+use crate::knn_search::{self, Neighborhood};
+use crate::neighbor_index::{self, IndexBackend};
+use crate::pca::{self, CovarianceOptions, NeighborhoodClass};
+use na::{Matrix3, Vector3};
+use ordered_float::OrderedFloat;
+use rayon::prelude::*;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+// How the sign of the estimated normals is chosen
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum NormalOrientation {
+    // Keep the sign given by the PCA sign correction
+    None,
+    // Point every normal towards the viewpoint
+    Viewpoint([f64; 3]),
+    // Propagate the orientation along a minimum spanning tree of the neighborhood graph,
+    // seeding each connected component with a normal pointing away from the cloud centroid
+    #[default]
+    Propagate,
+}
+
+#[derive(Clone, Debug)]
+pub struct LocalGeometryConfig {
+    pub neighborhood: Neighborhood,
+    pub index_backend: IndexBackend,
+    pub covariance: CovarianceOptions,
+    pub normal_orientation: NormalOrientation,
+}
+
+impl LocalGeometryConfig {
+    pub fn new(search_size: usize) -> Self {
+        Self {
+            neighborhood: Neighborhood::Knn(search_size),
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for LocalGeometryConfig {
+    fn default() -> Self {
+        Self {
+            neighborhood: Neighborhood::Knn(81),
+            index_backend: IndexBackend::KdTree,
+            covariance: CovarianceOptions::default(),
+            normal_orientation: NormalOrientation::Propagate,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PointGeometry {
+    // Variances along the principal axes, in descending order
+    pub eigenvalues: [f64; 3],
+    // Principal axes as columns, in the order of the eigenvalues. The third column is the
+    // normal, with the same sign
+    pub eigenvectors: Matrix3<f64>,
+    // Unit normal, the axis of least variance oriented as configured
+    pub normal: [f64; 3],
+    // l3 / (l1 + l2 + l3)
+    pub surface_variation: f64,
+    // (l1 - l2) / l1
+    pub linearity: f64,
+    // (l2 - l3) / l1
+    pub planarity: f64,
+    // l3 / l1
+    pub sphericity: f64,
+    // Rank of the neighborhood, normals of linear and degenerate neighborhoods are arbitrary
+    pub class: NeighborhoodClass,
+}
+
+fn ratio(numerator: f64, denominator: f64) -> f64 {
+    if denominator > 0. {
+        numerator / denominator
+    } else {
+        0.
+    }
+}
+
+fn point_geometry(
+    points: &[[f64; 3]],
+    query: &[f64; 3],
+    neighbors: &[usize],
+    covariance: &CovarianceOptions,
+) -> PointGeometry {
+    let neighborhood = neighbors
+        .iter()
+        .map(|&i| Vector3::from(points[i]))
+        .collect::<Vec<_>>();
+    let (eigenvalues, eigenvectors) =
+        pca::compute_pca3_with_options(&neighborhood, &Vector3::from(*query), covariance);
+    // Round-off can leave the smallest eigenvalues slightly negative
+    let [l1, l2, l3] = [0, 1, 2].map(|i| eigenvalues[i].max(0.));
+    let class = pca::classify_neighborhood(&eigenvalues, &neighborhood);
+    let normal = eigenvectors.column(2);
+    PointGeometry {
+        eigenvalues: [l1, l2, l3],
+        eigenvectors,
+        normal: [normal[0], normal[1], normal[2]],
+        surface_variation: ratio(l3, l1 + l2 + l3),
+        linearity: ratio(l1 - l2, l1),
+        planarity: ratio(l2 - l3, l1),
+        sphericity: ratio(l3, l1),
+        class,
+    }
+}
+
+impl PointGeometry {
+    // Reverses the normal along with the axis it comes from
+    fn flip_normal(&mut self) {
+        self.normal.iter_mut().for_each(|x| *x = -*x);
+        self.eigenvectors.column_mut(2).neg_mut();
+    }
+}
+
+fn dot(a: &[f64; 3], b: &[f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn orient_towards_viewpoint(
+    points: &[[f64; 3]],
+    geometry: &mut [PointGeometry],
+    viewpoint: &[f64; 3],
+) {
+    geometry.par_iter_mut().zip(points).for_each(|(g, point)| {
+        let to_viewpoint = [0, 1, 2].map(|j| viewpoint[j] - point[j]);
+        if dot(&g.normal, &to_viewpoint) < 0. {
+            g.flip_normal();
+        }
+    });
+}
+
+// Prim's algorithm on the neighborhood graph weighted by 1 - |n_i . n_j|, so that the
+// orientation is propagated between the most parallel normals first. A point is only pushed
+// again when its best edge weight improves
+fn propagate_orientation(
+    points: &[[f64; 3]],
+    geometry: &mut [PointGeometry],
+    neighbors: &[Vec<usize>],
+) {
+    let npoints = points.len();
+    let centroid = points
+        .iter()
+        .fold(Vector3::zeros(), |acc, p| acc + Vector3::from(*p))
+        / npoints as f64;
+    let mut visited = vec![false; npoints];
+    let mut best_weight = vec![f64::INFINITY; npoints];
+    let mut heap = BinaryHeap::new();
+    for seed in 0..npoints {
+        if visited[seed] {
+            continue;
+        }
+        let outwards = [0, 1, 2].map(|j| points[seed][j] - centroid[j]);
+        if dot(&geometry[seed].normal, &outwards) < 0. {
+            geometry[seed].flip_normal();
+        }
+        best_weight[seed] = 0.;
+        heap.push((Reverse(OrderedFloat(0.)), Reverse(seed), seed));
+        while let Some((_, Reverse(i), parent)) = heap.pop() {
+            if visited[i] {
+                continue;
+            }
+            visited[i] = true;
+            if dot(&geometry[i].normal, &geometry[parent].normal) < 0. {
+                geometry[i].flip_normal();
+            }
+            for &j in &neighbors[i] {
+                let weight = 1. - dot(&geometry[i].normal, &geometry[j].normal).abs();
+                if !visited[j] && weight < best_weight[j] {
+                    best_weight[j] = weight;
+                    heap.push((Reverse(OrderedFloat(weight)), Reverse(j), i));
+                }
+            }
+        }
+    }
+}
+
+// Per-point eigen decomposition of the local covariance, normals and eigenvalue based
+// shape descriptors, using the same neighborhood queries and PCA as the PointPCA2 features
+pub fn compute_local_geometry(
+    points: &[[f64; 3]],
+    config: &LocalGeometryConfig,
+) -> Vec<PointGeometry> {
+    if points.is_empty() {
+        return Vec::new();
+    }
+    let neighborhood = config.neighborhood.clamped(points.len());
+    let index = neighbor_index::build_index(points, &config.index_backend);
+    let neighbors = points
+        .par_iter()
+        .map(|point| knn_search::neighborhood(index.as_ref(), point, &neighborhood))
+        .collect::<Vec<_>>();
+    let mut geometry = neighbors
+        .par_iter()
+        .zip(points)
+        .map(|(indices, query)| point_geometry(points, query, indices, &config.covariance))
+        .collect::<Vec<_>>();
+    match config.normal_orientation {
+        NormalOrientation::None => {}
+        NormalOrientation::Viewpoint(viewpoint) => {
+            orient_towards_viewpoint(points, &mut geometry, &viewpoint)
+        }
+        NormalOrientation::Propagate => propagate_orientation(points, &mut geometry, &neighbors),
+    }
+    geometry
+}
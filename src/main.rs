@@ -1,4 +1,3 @@
-use pointpca2_rs;
 use pointpca2_rs::ply_manager;
 
 fn main() {
@@ -19,5 +18,5 @@ fn main() {
     for col in pooled_predictors.iter() {
         print!("{:.4}  ", *col);
     }
-    println!("");
+    println!();
 }
@@ -0,0 +1,558 @@
+use crate::knn_search::NO_NEIGHBOR;
+use rayon::prelude::*;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum IndexBackend {
+    #[default]
+    KdTree,
+    // Uniform hash grid, the cell size is estimated from the cloud extent when not given
+    VoxelGrid {
+        cell_size: Option<f64>,
+    },
+    // Exhaustive search, mainly useful as a reference in tests
+    BruteForce,
+    // Approximate kd-tree search evaluating at most max_checks points per query.
+    // Larger values trade speed for recall, see knn_search::measure_recall
+    ApproximateKdTree {
+        max_checks: usize,
+    },
+}
+
+// Spatial index over a borrowed cloud. Queries return (index, squared distance)
+// pairs sorted by increasing distance, then index, so equidistant neighbors come
+// back in the same order from every backend.
+pub trait NeighborIndex<'a>: Sync {
+    fn build(points: &'a [[f64; 3]]) -> Self
+    where
+        Self: Sized;
+
+    fn points(&self) -> &'a [[f64; 3]];
+
+    fn knn(&self, point: &[f64; 3], k: usize) -> Vec<(usize, f64)>;
+
+    // The indices.len() nearest neighbors written in place, in the order of knn, with their
+    // squared distances when distances is given. Slots past the found neighbors hold
+    // NO_NEIGHBOR and an infinite distance. The default implementation goes through knn
+    fn knn_into(&self, point: &[f64; 3], indices: &mut [usize], distances: Option<&mut [f64]>) {
+        let mut row = NeighborRow::new(self.points(), point, indices, distances);
+        for neighbor in self.knn(point, row.capacity()) {
+            row.insert(neighbor);
+        }
+    }
+
+    // Neighbors strictly closer than radius
+    fn radius(&self, point: &[f64; 3], radius: f64) -> Vec<(usize, f64)>;
+}
+
+pub fn build_index<'a>(
+    points: &'a [[f64; 3]],
+    backend: &IndexBackend,
+) -> Box<dyn NeighborIndex<'a> + 'a> {
+    match *backend {
+        IndexBackend::KdTree => {
+            Box::new(<kd_tree::KdIndexTree3<[f64; 3]>>::par_build_by_ordered_float(points))
+        }
+        IndexBackend::VoxelGrid { cell_size } => match cell_size {
+            Some(cell_size) => Box::new(VoxelGrid::with_cell_size(points, cell_size)),
+            None => Box::new(VoxelGrid::build(points)),
+        },
+        IndexBackend::BruteForce => Box::new(BruteForce::build(points)),
+        IndexBackend::ApproximateKdTree { max_checks } => {
+            Box::new(ApproximateKdTree::with_max_checks(points, max_checks))
+        }
+    }
+}
+
+pub fn squared_distance(a: &[f64; 3], b: &[f64; 3]) -> f64 {
+    (a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)
+}
+
+fn neighbor_order(a: &(usize, f64), b: &(usize, f64)) -> Ordering {
+    a.1.total_cmp(&b.1).then(a.0.cmp(&b.0))
+}
+
+fn sort_by_distance(neighbors: &mut [(usize, f64)]) {
+    neighbors.sort_unstable_by(neighbor_order);
+}
+
+// The k smallest candidates by distance, then index, kept sorted in caller buffers of
+// length k. Without a distances buffer, distances are recomputed from the points
+struct NeighborRow<'r> {
+    points: &'r [[f64; 3]],
+    query: &'r [f64; 3],
+    indices: &'r mut [usize],
+    distances: Option<&'r mut [f64]>,
+    len: usize,
+}
+
+impl<'r> NeighborRow<'r> {
+    fn new(
+        points: &'r [[f64; 3]],
+        query: &'r [f64; 3],
+        indices: &'r mut [usize],
+        mut distances: Option<&'r mut [f64]>,
+    ) -> Self {
+        indices.fill(NO_NEIGHBOR);
+        if let Some(distances) = distances.as_deref_mut() {
+            assert_eq!(
+                distances.len(),
+                indices.len(),
+                "distances must hold one entry per index."
+            );
+            distances.fill(f64::INFINITY);
+        }
+        Self {
+            points,
+            query,
+            indices,
+            distances,
+            len: 0,
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        self.indices.len()
+    }
+
+    fn is_full(&self) -> bool {
+        self.len == self.capacity()
+    }
+
+    fn get(&self, i: usize) -> (usize, f64) {
+        let idx = self.indices[i];
+        match self.distances.as_deref() {
+            Some(distances) => (idx, distances[i]),
+            None => (idx, squared_distance(self.query, &self.points[idx])),
+        }
+    }
+
+    fn worst_distance(&self) -> f64 {
+        if self.is_full() && self.len > 0 {
+            self.get(self.len - 1).1
+        } else {
+            f64::INFINITY
+        }
+    }
+
+    fn insert(&mut self, candidate: (usize, f64)) {
+        let k = self.capacity();
+        if k == 0 || (self.is_full() && neighbor_order(&candidate, &self.get(k - 1)).is_ge()) {
+            return;
+        }
+        let (mut lower, mut upper) = (0, self.len);
+        while lower < upper {
+            let mid = (lower + upper) / 2;
+            if neighbor_order(&self.get(mid), &candidate).is_lt() {
+                lower = mid + 1;
+            } else {
+                upper = mid;
+            }
+        }
+        // The last neighbor drops out of a full row
+        let end = self.len.min(k - 1);
+        self.indices.copy_within(lower..end, lower + 1);
+        self.indices[lower] = candidate.0;
+        if let Some(distances) = self.distances.as_deref_mut() {
+            distances.copy_within(lower..end, lower + 1);
+            distances[lower] = candidate.1;
+        }
+        self.len = (self.len + 1).min(k);
+    }
+}
+
+// knn through knn_into, for the backends that search in place
+fn knn_from_row<'a>(
+    index: &impl NeighborIndex<'a>,
+    point: &[f64; 3],
+    k: usize,
+) -> Vec<(usize, f64)> {
+    let mut indices = vec![NO_NEIGHBOR; k];
+    let mut distances = vec![f64::INFINITY; k];
+    index.knn_into(point, &mut indices, Some(&mut distances));
+    indices
+        .into_iter()
+        .zip(distances)
+        .take_while(|&(idx, _)| idx != NO_NEIGHBOR)
+        .collect()
+}
+
+struct KdSearch<'s> {
+    items: &'s [usize],
+    source: &'s [[f64; 3]],
+    query: &'s [f64; 3],
+    max_checks: usize,
+    checks: usize,
+    neighbors: NeighborRow<'s>,
+}
+
+impl KdSearch<'_> {
+    fn exhausted(&self) -> bool {
+        self.checks >= self.max_checks && self.neighbors.is_full()
+    }
+
+    fn recurse(&mut self, start: usize, end: usize, axis: usize) {
+        if start >= end || self.exhausted() {
+            return;
+        }
+        let mid = start + (end - start) / 2;
+        let idx = self.items[mid];
+        let dist = squared_distance(self.query, &self.source[idx]);
+        self.checks += 1;
+        self.neighbors.insert((idx, dist));
+        let diff = self.query[axis] - self.source[idx][axis];
+        let next_axis = (axis + 1) % 3;
+        let (near, far) = if diff < 0. {
+            ((start, mid), (mid + 1, end))
+        } else {
+            ((mid + 1, end), (start, mid))
+        };
+        self.recurse(near.0, near.1, next_axis);
+        // Non-strict, an equidistant neighbor with a lower index may lie on the far side
+        if diff * diff <= self.neighbors.worst_distance() {
+            self.recurse(far.0, far.1, next_axis);
+        }
+    }
+}
+
+fn kd_knn_into(
+    tree: &kd_tree::KdIndexTree3<[f64; 3]>,
+    point: &[f64; 3],
+    max_checks: usize,
+    indices: &mut [usize],
+    distances: Option<&mut [f64]>,
+) {
+    let items = tree.indices().items();
+    let neighbors = NeighborRow::new(tree.source(), point, indices, distances);
+    if neighbors.capacity() == 0 || items.is_empty() {
+        return;
+    }
+    let mut search = KdSearch {
+        items,
+        source: tree.source(),
+        query: point,
+        max_checks,
+        checks: 0,
+        neighbors,
+    };
+    search.recurse(0, items.len(), 0);
+}
+
+impl<'a> NeighborIndex<'a> for kd_tree::KdIndexTree3<'a, [f64; 3]> {
+    fn build(points: &'a [[f64; 3]]) -> Self {
+        kd_tree::KdIndexTree3::par_build_by_ordered_float(points)
+    }
+
+    fn points(&self) -> &'a [[f64; 3]] {
+        self.source()
+    }
+
+    fn knn(&self, point: &[f64; 3], k: usize) -> Vec<(usize, f64)> {
+        knn_from_row(self, point, k)
+    }
+
+    fn knn_into(&self, point: &[f64; 3], indices: &mut [usize], distances: Option<&mut [f64]>) {
+        kd_knn_into(self, point, usize::MAX, indices, distances)
+    }
+
+    fn radius(&self, point: &[f64; 3], radius: f64) -> Vec<(usize, f64)> {
+        let source = self.source();
+        let mut neighbors = self
+            .within_radius(point, radius)
+            .iter()
+            .map(|&&idx| (idx, squared_distance(point, &source[idx])))
+            .collect::<Vec<(usize, f64)>>();
+        sort_by_distance(&mut neighbors);
+        neighbors
+    }
+}
+
+pub struct BruteForce<'a> {
+    points: &'a [[f64; 3]],
+}
+
+impl<'a> NeighborIndex<'a> for BruteForce<'a> {
+    fn build(points: &'a [[f64; 3]]) -> Self {
+        Self { points }
+    }
+
+    fn points(&self) -> &'a [[f64; 3]] {
+        self.points
+    }
+
+    fn knn(&self, point: &[f64; 3], k: usize) -> Vec<(usize, f64)> {
+        let mut neighbors = self
+            .points
+            .iter()
+            .enumerate()
+            .map(|(idx, other)| (idx, squared_distance(point, other)))
+            .collect::<Vec<(usize, f64)>>();
+        if k < neighbors.len() {
+            neighbors.select_nth_unstable_by(k, neighbor_order);
+            neighbors.truncate(k);
+        }
+        sort_by_distance(&mut neighbors);
+        neighbors
+    }
+
+    fn knn_into(&self, point: &[f64; 3], indices: &mut [usize], distances: Option<&mut [f64]>) {
+        let mut row = NeighborRow::new(self.points, point, indices, distances);
+        for (idx, other) in self.points.iter().enumerate() {
+            row.insert((idx, squared_distance(point, other)));
+        }
+    }
+
+    fn radius(&self, point: &[f64; 3], radius: f64) -> Vec<(usize, f64)> {
+        let mut neighbors = self
+            .points
+            .iter()
+            .enumerate()
+            .map(|(idx, other)| (idx, squared_distance(point, other)))
+            .filter(|&(_, dist)| dist < radius * radius)
+            .collect::<Vec<(usize, f64)>>();
+        sort_by_distance(&mut neighbors);
+        neighbors
+    }
+}
+
+type Cell = [i64; 3];
+
+pub struct VoxelGrid<'a> {
+    points: &'a [[f64; 3]],
+    cell_size: f64,
+    cells: HashMap<Cell, Vec<usize>>,
+    min_cell: Cell,
+    max_cell: Cell,
+}
+
+impl<'a> VoxelGrid<'a> {
+    pub fn with_cell_size(points: &'a [[f64; 3]], cell_size: f64) -> Self {
+        assert!(cell_size > 0., "cell_size must be positive.");
+        let cell_keys = points
+            .par_iter()
+            .map(|point| point.map(|x| (x / cell_size).floor() as i64))
+            .collect::<Vec<Cell>>();
+        let mut cells: HashMap<Cell, Vec<usize>> = HashMap::new();
+        let mut min_cell = [i64::MAX; 3];
+        let mut max_cell = [i64::MIN; 3];
+        for (idx, cell) in cell_keys.into_iter().enumerate() {
+            for j in 0..3 {
+                min_cell[j] = min_cell[j].min(cell[j]);
+                max_cell[j] = max_cell[j].max(cell[j]);
+            }
+            cells.entry(cell).or_default().push(idx);
+        }
+        Self {
+            points,
+            cell_size,
+            cells,
+            min_cell,
+            max_cell,
+        }
+    }
+
+    // Roughly a few tens of points per occupied cell for surface-like clouds
+    fn estimate_cell_size(points: &[[f64; 3]]) -> f64 {
+        let mut lower = [f64::INFINITY; 3];
+        let mut upper = [f64::NEG_INFINITY; 3];
+        for point in points {
+            for j in 0..3 {
+                lower[j] = lower[j].min(point[j]);
+                upper[j] = upper[j].max(point[j]);
+            }
+        }
+        let max_extent = (0..3).map(|j| upper[j] - lower[j]).fold(0., f64::max);
+        let cell_size = max_extent / (points.len() as f64).cbrt();
+        if cell_size.is_finite() && cell_size > 0. {
+            cell_size
+        } else {
+            1.
+        }
+    }
+
+    fn cell_of(&self, point: &[f64; 3]) -> Cell {
+        point.map(|x| (x / self.cell_size).floor() as i64)
+    }
+
+    // Ring of cell around center, its Chebyshev distance in cells
+    fn ring_of(cell: &Cell, center: &Cell) -> i64 {
+        (0..3)
+            .map(|j| (cell[j] - center[j]).abs())
+            .max()
+            .unwrap_or(0)
+    }
+
+    // Number of cells in the ring, (2 ring + 1)^3 - (2 ring - 1)^3
+    fn ring_cells(ring: i64) -> usize {
+        if ring == 0 {
+            1
+        } else {
+            (24 * ring * ring + 2) as usize
+        }
+    }
+
+    // Rings around the cell before this one hold no occupied cell
+    fn min_ring(&self, center: &Cell) -> i64 {
+        (0..3)
+            .map(|j| (self.min_cell[j] - center[j]).max(center[j] - self.max_cell[j]))
+            .max()
+            .unwrap_or(0)
+            .max(0)
+    }
+
+    // Number of rings around the cell after which every occupied cell has been visited
+    fn max_ring(&self, center: &Cell) -> i64 {
+        (0..3)
+            .map(|j| (center[j] - self.min_cell[j]).max(self.max_cell[j] - center[j]))
+            .max()
+            .unwrap_or(0)
+            .max(0)
+    }
+
+    // Visits the rings from first_ring to last_ring, until done holds after a ring. Once a
+    // ring has more cells than the grid has occupied ones, the remaining occupied cells are
+    // visited directly instead, in a single step
+    fn visit_rings<S>(
+        &self,
+        center: &Cell,
+        (first_ring, last_ring): (i64, i64),
+        state: &mut S,
+        visit: impl Fn(&mut S, &[usize]),
+        done: impl Fn(&S, i64) -> bool,
+    ) {
+        for ring in first_ring..=last_ring {
+            if Self::ring_cells(ring) > self.cells.len() {
+                for (cell, indices) in &self.cells {
+                    let cell_ring = Self::ring_of(cell, center);
+                    if cell_ring >= ring && cell_ring <= last_ring {
+                        visit(state, indices);
+                    }
+                }
+                return;
+            }
+            self.visit_ring(center, ring, |indices| visit(state, indices));
+            if done(state, ring) {
+                return;
+            }
+        }
+    }
+
+    fn visit_ring(&self, center: &Cell, ring: i64, mut visit: impl FnMut(&[usize])) {
+        for dx in -ring..=ring {
+            for dy in -ring..=ring {
+                // Inside the shell only the two z faces belong to this ring
+                let on_xy_face = dx.abs() == ring || dy.abs() == ring;
+                let dz_step = if on_xy_face { 1 } else { (2 * ring).max(1) };
+                for dz in (-ring..=ring).step_by(dz_step as usize) {
+                    let cell = [center[0] + dx, center[1] + dy, center[2] + dz];
+                    if let Some(indices) = self.cells.get(&cell) {
+                        visit(indices);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<'a> NeighborIndex<'a> for VoxelGrid<'a> {
+    fn build(points: &'a [[f64; 3]]) -> Self {
+        Self::with_cell_size(points, Self::estimate_cell_size(points))
+    }
+
+    fn points(&self) -> &'a [[f64; 3]] {
+        self.points
+    }
+
+    fn knn(&self, point: &[f64; 3], k: usize) -> Vec<(usize, f64)> {
+        knn_from_row(self, point, k)
+    }
+
+    fn knn_into(&self, point: &[f64; 3], indices: &mut [usize], distances: Option<&mut [f64]>) {
+        let mut neighbors = NeighborRow::new(self.points, point, indices, distances);
+        if neighbors.capacity() == 0 || self.points.is_empty() {
+            return;
+        }
+        let center = self.cell_of(point);
+        self.visit_rings(
+            &center,
+            (self.min_ring(&center), self.max_ring(&center)),
+            &mut neighbors,
+            |neighbors, indices| {
+                for &idx in indices {
+                    neighbors.insert((idx, squared_distance(point, &self.points[idx])));
+                }
+            },
+            |neighbors, ring| {
+                // Every point outside the visited rings is at least ring * cell_size away
+                let covered = ring as f64 * self.cell_size;
+                neighbors.is_full() && neighbors.worst_distance() < covered * covered
+            },
+        );
+    }
+
+    fn radius(&self, point: &[f64; 3], radius: f64) -> Vec<(usize, f64)> {
+        if self.points.is_empty() {
+            return Vec::new();
+        }
+        let center = self.cell_of(point);
+        let max_ring = ((radius / self.cell_size).ceil() as i64).min(self.max_ring(&center));
+        let mut neighbors = Vec::new();
+        self.visit_rings(
+            &center,
+            (self.min_ring(&center), max_ring),
+            &mut neighbors,
+            |neighbors, indices| {
+                for &idx in indices {
+                    let dist = squared_distance(point, &self.points[idx]);
+                    if dist < radius * radius {
+                        neighbors.push((idx, dist));
+                    }
+                }
+            },
+            |_, _| false,
+        );
+        sort_by_distance(&mut neighbors);
+        neighbors
+    }
+}
+
+// Kd-tree search that stops descending into further subtrees once max_checks distance
+// evaluations have been spent and k candidates are found. Radius queries remain exact.
+pub struct ApproximateKdTree<'a> {
+    tree: kd_tree::KdIndexTree3<'a, [f64; 3]>,
+    max_checks: usize,
+}
+
+impl<'a> ApproximateKdTree<'a> {
+    pub const DEFAULT_MAX_CHECKS: usize = 256;
+
+    pub fn with_max_checks(points: &'a [[f64; 3]], max_checks: usize) -> Self {
+        Self {
+            tree: kd_tree::KdIndexTree3::par_build_by_ordered_float(points),
+            max_checks,
+        }
+    }
+}
+
+impl<'a> NeighborIndex<'a> for ApproximateKdTree<'a> {
+    fn build(points: &'a [[f64; 3]]) -> Self {
+        Self::with_max_checks(points, Self::DEFAULT_MAX_CHECKS)
+    }
+
+    fn points(&self) -> &'a [[f64; 3]] {
+        self.tree.source()
+    }
+
+    fn knn(&self, point: &[f64; 3], k: usize) -> Vec<(usize, f64)> {
+        knn_from_row(self, point, k)
+    }
+
+    fn knn_into(&self, point: &[f64; 3], indices: &mut [usize], distances: Option<&mut [f64]>) {
+        kd_knn_into(&self.tree, point, self.max_checks, indices, distances)
+    }
+
+    fn radius(&self, point: &[f64; 3], radius: f64) -> Vec<(usize, f64)> {
+        self.tree.radius(point, radius)
+    }
+}
@@ -1,12 +1,274 @@
+use crate::precision::Real;
 use crate::utils;
-use na::DMatrix;
+use na::{DMatrix, Matrix3, Vector3};
+
+// Upper bound on Jacobi sweeps, convergence usually takes fewer than ten
+const JACOBI_MAX_SWEEPS: usize = 32;
+// Huber threshold on the Mahalanobis distance, square root of the 95% quantile of a
+// chi-squared distribution with 3 degrees of freedom
+const HUBER_THRESHOLD: f64 = 2.7954834829151074;
+// Variances below this fraction of the largest one are floored when inverting a covariance
+const VARIANCE_FLOOR: f64 = 1e-9;
+// Concentration steps of the trimmed estimator
+const TRIMMED_STEPS: usize = 3;
+// Eigenvalues below this fraction of the largest one are treated as zero when classifying
+pub const RANK_TOLERANCE: f64 = 1e-10;
+// Reduced precisions round eigenvalues to about this many machine epsilons of the largest
+const ROUND_OFF_EPSILONS: f64 = 100.;
+
+// Rank of a neighborhood covariance, ordered from the best to the worst conditioned, then
+// Unclassified
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum NeighborhoodClass {
+    FullRank,
+    // Coplanar neighbors, no spread along the normal
+    Planar,
+    // Collinear neighbors, only the first axis is defined
+    Linear,
+    // Duplicated (or single) neighbors, no axis is defined
+    Degenerate,
+    // Not computed, texture-only runs that keep every neighborhood skip the PCA
+    Unclassified,
+}
+
+// Handling of neighborhoods whose class is at least `from` (Planar covers planar, linear
+// and degenerate neighborhoods)
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum DegeneratePolicy {
+    // Use them as any other neighborhood
+    #[default]
+    Keep,
+    // Exclude their local features from pooling
+    Skip {
+        from: NeighborhoodClass,
+    },
+    // Requery them with twice as many nearest neighbors until their class improves or
+    // max_k is reached
+    Expand {
+        from: NeighborhoodClass,
+        max_k: usize,
+    },
+}
+
+// Number of points of each neighborhood class
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NeighborhoodClassCounts {
+    pub full_rank: usize,
+    pub planar: usize,
+    pub linear: usize,
+    pub degenerate: usize,
+    pub unclassified: usize,
+}
+
+impl NeighborhoodClassCounts {
+    pub fn from_classes(classes: &[NeighborhoodClass]) -> Self {
+        let mut counts = Self::default();
+        for class in classes {
+            match class {
+                NeighborhoodClass::FullRank => counts.full_rank += 1,
+                NeighborhoodClass::Planar => counts.planar += 1,
+                NeighborhoodClass::Linear => counts.linear += 1,
+                NeighborhoodClass::Degenerate => counts.degenerate += 1,
+                NeighborhoodClass::Unclassified => counts.unclassified += 1,
+            }
+        }
+        counts
+    }
+
+    pub fn merge(&mut self, other: &Self) {
+        self.full_rank += other.full_rank;
+        self.planar += other.planar;
+        self.linear += other.linear;
+        self.degenerate += other.degenerate;
+        self.unclassified += other.unclassified;
+    }
+}
+
+// Weight given to each neighbor before any robust reweighting
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum CovarianceWeighting {
+    #[default]
+    Uniform,
+    // exp(-d^2 / (2 sigma^2)) of the distance d to the query point, sigma defaults to the
+    // root mean square distance of the neighborhood
+    Gaussian {
+        sigma: Option<f64>,
+    },
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum CovarianceEstimator {
+    #[default]
+    Standard,
+    // Iteratively reweighted least squares with Huber weights on the Mahalanobis distance
+    Irls {
+        iterations: usize,
+    },
+    // Keeps the (1 - fraction) neighbors closest in Mahalanobis distance
+    Trimmed {
+        fraction: f64,
+    },
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CovarianceOptions {
+    pub weighting: CovarianceWeighting,
+    pub estimator: CovarianceEstimator,
+    // Divide by n - 1 (or its weighted equivalent) instead of n
+    pub unbiased: bool,
+}
 
 fn compute_covariance_matrix(x: &DMatrix<f64>, unbiased: bool) -> DMatrix<f64> {
-    let bias = if unbiased { 1. } else { 0. };
+    let bias = if unbiased && x.nrows() > 1 { 1. } else { 0. };
     let nrows = x.nrows() as f64;
     let means = x.row_mean();
-    let centered = utils::subtract_row_from_matrix(&x, &means);
-    let covariance_matrix = (&centered.transpose() * &centered) / (nrows - bias);
+    let centered = utils::subtract_row_from_matrix(x, &means);
+    (&centered.transpose() * &centered) / (nrows - bias)
+}
+
+fn compute_covariance_matrix3<T: Real>(x: &[Vector3<T>], unbiased: bool) -> Matrix3<T> {
+    // A single row has no unbiased estimate, the biased one (zero) is used instead
+    let bias = if unbiased && x.len() > 1 { 1. } else { 0. };
+    let nrows = x.len();
+    let mut means = Vector3::zeros();
+    for row in x {
+        means += row;
+    }
+    means /= T::cast(nrows as f64);
+    let mut covariance_matrix = Matrix3::zeros();
+    for row in x {
+        let centered = row - means;
+        covariance_matrix += centered * centered.transpose();
+    }
+    covariance_matrix / T::cast(nrows as f64 - bias)
+}
+
+// Weighted mean and covariance, with reliability weights when unbiased
+fn compute_weighted_covariance_matrix3<T: Real>(
+    x: &[Vector3<T>],
+    weights: &[T],
+    unbiased: bool,
+) -> (Vector3<T>, Matrix3<T>) {
+    let sum_weights = weights.iter().fold(T::zero(), |acc, &w| acc + w);
+    let means = x
+        .iter()
+        .zip(weights)
+        .fold(Vector3::zeros(), |acc, (row, &w)| acc + row * w)
+        / sum_weights;
+    let mut covariance_matrix = Matrix3::zeros();
+    for (row, &w) in x.iter().zip(weights) {
+        let centered = row - means;
+        covariance_matrix += centered * centered.transpose() * w;
+    }
+    let reliability =
+        sum_weights - weights.iter().fold(T::zero(), |acc, &w| acc + w * w) / sum_weights;
+    // Without at least two weighted rows, the biased estimate is used
+    let normalization = if unbiased && reliability > T::zero() {
+        reliability
+    } else {
+        sum_weights
+    };
+    (means, covariance_matrix / normalization)
+}
+
+fn distance_weights<T: Real>(
+    x: &[Vector3<T>],
+    center: &Vector3<T>,
+    weighting: &CovarianceWeighting,
+) -> Vec<T> {
+    let nrows = x.len();
+    match *weighting {
+        CovarianceWeighting::Uniform => vec![T::one(); nrows],
+        CovarianceWeighting::Gaussian { sigma } => {
+            let squared_distances = x
+                .iter()
+                .map(|row| (0..3).fold(T::zero(), |acc, j| acc + (row[j] - center[j]).powi(2)))
+                .collect::<Vec<_>>();
+            let variance = match sigma {
+                Some(sigma) => T::cast(sigma * sigma),
+                None => {
+                    squared_distances.iter().fold(T::zero(), |acc, &d| acc + d)
+                        / T::cast(nrows as f64)
+                }
+            };
+            let weights = squared_distances
+                .iter()
+                .map(|&d| (-d / (T::cast(2.) * variance)).exp())
+                .collect::<Vec<_>>();
+            // Degenerate scales (or every weight underflowing) fall back to uniform weights
+            if variance > T::zero() && weights.iter().any(|&w| w > T::zero()) {
+                weights
+            } else {
+                vec![T::one(); nrows]
+            }
+        }
+    }
+}
+
+// Squared Mahalanobis distances of the rows, flooring near-zero variances so that planar
+// and linear neighborhoods remain invertible
+fn mahalanobis_distances<T: Real>(
+    x: &[Vector3<T>],
+    means: &Vector3<T>,
+    covariance: &Matrix3<T>,
+) -> Vec<T> {
+    let (variances, axes) = symmetric_eigen3(*covariance);
+    let floor = variances.max().max(T::zero()) * T::cast(VARIANCE_FLOOR);
+    x.iter()
+        .map(|row| {
+            let centered = row - means;
+            (0..3)
+                .map(|k| axes.column(k).dot(&centered).powi(2) / variances[k].max(floor))
+                .filter(|d| d.is_finite())
+                .fold(T::zero(), |acc, d| acc + d)
+        })
+        .collect()
+}
+
+fn compute_robust_covariance_matrix3<T: Real>(
+    x: &[Vector3<T>],
+    center: &Vector3<T>,
+    options: &CovarianceOptions,
+) -> Matrix3<T> {
+    let base_weights = distance_weights(x, center, &options.weighting);
+    let (mut means, mut covariance_matrix) =
+        compute_weighted_covariance_matrix3(x, &base_weights, options.unbiased);
+    let steps = match options.estimator {
+        CovarianceEstimator::Standard => 0,
+        CovarianceEstimator::Irls { iterations } => iterations,
+        CovarianceEstimator::Trimmed { .. } => TRIMMED_STEPS,
+    };
+    let mut weights = base_weights.clone();
+    for _ in 0..steps {
+        let distances = mahalanobis_distances(x, &means, &covariance_matrix);
+        match options.estimator {
+            CovarianceEstimator::Irls { .. } => {
+                let threshold = T::cast(HUBER_THRESHOLD);
+                for ((w, &base), &d) in weights.iter_mut().zip(&base_weights).zip(&distances) {
+                    let d = d.sqrt();
+                    *w = if d <= threshold {
+                        base
+                    } else {
+                        base * threshold / d
+                    };
+                }
+            }
+            CovarianceEstimator::Trimmed { fraction } => {
+                // At least 3 neighbors are kept so that the covariance stays defined
+                let nkept = ((x.len() as f64 * (1. - fraction)).ceil() as usize)
+                    .clamp(3.min(x.len()), x.len());
+                let mut order = (0..x.len()).collect::<Vec<_>>();
+                order.sort_by(|&a, &b| distances[a].total_cmp(&distances[b]).then(a.cmp(&b)));
+                weights.fill(T::zero());
+                for &i in &order[..nkept] {
+                    weights[i] = base_weights[i];
+                }
+            }
+            CovarianceEstimator::Standard => {}
+        }
+        (means, covariance_matrix) =
+            compute_weighted_covariance_matrix3(x, &weights, options.unbiased);
+    }
     covariance_matrix
 }
 
@@ -25,11 +287,22 @@ fn eigen_sign_correction(mut u: DMatrix<f64>) -> DMatrix<f64> {
     u
 }
 
-fn compute_eigenvectors(matrix: DMatrix<f64>) -> DMatrix<f64> {
-    let eigen = matrix.symmetric_eigen();
-    // Sort indices by eigenvalues in descending order (largest first)
+fn eigen_sign_correction3<T: Real>(mut u: Matrix3<T>) -> Matrix3<T> {
+    for i in 0..3 {
+        let sign = u
+            .column(i)
+            .iter()
+            .max_by(|&&a, &&b| a.abs().total_cmp(&b.abs()))
+            .unwrap()
+            .signum();
+        u.column_mut(i).apply(|x| *x *= sign);
+    }
+    u
+}
+
+// Indices of the eigenvalues in descending order (largest first)
+fn sort_eigenvalues<T: Real>(eigenvalues: &Vector3<T>) -> [usize; 3] {
     let mut indices = [0, 1, 2];
-    let eigenvalues = &eigen.eigenvalues;
     // Manual sorting for 3x3 matrix - faster than Vec allocation and sort
     if eigenvalues[indices[0]] < eigenvalues[indices[1]] {
         indices.swap(0, 1);
@@ -40,6 +313,13 @@ fn compute_eigenvectors(matrix: DMatrix<f64>) -> DMatrix<f64> {
     if eigenvalues[indices[0]] < eigenvalues[indices[1]] {
         indices.swap(0, 1);
     }
+    indices
+}
+
+fn compute_eigenvectors(matrix: DMatrix<f64>) -> DMatrix<f64> {
+    let eigen = matrix.symmetric_eigen();
+    let eigenvalues = Vector3::from_column_slice(eigen.eigenvalues.as_slice());
+    let indices = sort_eigenvalues(&eigenvalues);
     let sorted_eigenvectors = DMatrix::from_columns(
         &indices
             .iter()
@@ -47,12 +327,127 @@ fn compute_eigenvectors(matrix: DMatrix<f64>) -> DMatrix<f64> {
             .collect::<Vec<_>>(),
     );
     // Sign correction for eigenvectors
-    let u_corrected = eigen_sign_correction(sorted_eigenvectors);
-    u_corrected
+    eigen_sign_correction(sorted_eigenvectors)
 }
 
-pub fn compute_pca(matrix: &DMatrix<f64>) -> DMatrix<f64> {
+// Cyclic Jacobi eigen decomposition of a symmetric 3x3 matrix, returning the
+// eigenvalues and the eigenvectors as columns
+fn symmetric_eigen3<T: Real>(mut a: Matrix3<T>) -> (Vector3<T>, Matrix3<T>) {
+    let mut v = Matrix3::identity();
+    // Beyond this, theta squared could overflow
+    let large_theta = T::cast(1e150).min(T::max_value().unwrap().sqrt());
+    let (one, two, half) = (T::one(), T::cast(2.), T::cast(0.5));
+    for _ in 0..JACOBI_MAX_SWEEPS {
+        let off_diagonal = a[(0, 1)].powi(2) + a[(0, 2)].powi(2) + a[(1, 2)].powi(2);
+        if off_diagonal <= T::default_epsilon().powi(2) * a.norm_squared() {
+            break;
+        }
+        for (p, q) in [(0, 1), (0, 2), (1, 2)] {
+            let apq = a[(p, q)];
+            if apq == T::zero() {
+                continue;
+            }
+            let theta = (a[(q, q)] - a[(p, p)]) / (two * apq);
+            let t = if theta.abs() > large_theta {
+                half / theta
+            } else {
+                theta.signum() / (theta.abs() + (theta * theta + one).sqrt())
+            };
+            let c = one / (t * t + one).sqrt();
+            let s = t * c;
+            let mut rotation = Matrix3::identity();
+            rotation[(p, p)] = c;
+            rotation[(q, q)] = c;
+            rotation[(p, q)] = s;
+            rotation[(q, p)] = -s;
+            a = rotation.transpose() * a * rotation;
+            v *= rotation;
+        }
+    }
+    (a.diagonal(), v)
+}
+
+fn compute_eigen3<T: Real>(matrix: Matrix3<T>) -> (Vector3<T>, Matrix3<T>) {
+    let (eigenvalues, eigenvectors) = symmetric_eigen3(matrix);
+    let indices = sort_eigenvalues(&eigenvalues);
+    let sorted_eigenvalues = Vector3::new(
+        eigenvalues[indices[0]],
+        eigenvalues[indices[1]],
+        eigenvalues[indices[2]],
+    );
+    let sorted_eigenvectors = Matrix3::from_columns(&[
+        eigenvectors.column(indices[0]),
+        eigenvectors.column(indices[1]),
+        eigenvectors.column(indices[2]),
+    ]);
+    (
+        sorted_eigenvalues,
+        eigen_sign_correction3(sorted_eigenvectors),
+    )
+}
+
+// Reference implementation on dynamically sized matrices and the general symmetric
+// eigen solver of nalgebra
+pub fn compute_pca_dynamic(matrix: &DMatrix<f64>) -> DMatrix<f64> {
     let covariance_matrix = compute_covariance_matrix(matrix, false);
-    let pca_matrix = compute_eigenvectors(covariance_matrix);
-    pca_matrix
+    compute_eigenvectors(covariance_matrix)
+}
+
+// Principal axes of an n x 3 matrix as columns, sorted by decreasing variance
+pub fn compute_pca3(matrix: &DMatrix<f64>) -> Matrix3<f64> {
+    compute_pca3_with_eigenvalues(matrix).1
+}
+
+// Variances along the principal axes (descending) together with the axes as columns
+pub fn compute_pca3_with_eigenvalues(matrix: &DMatrix<f64>) -> (Vector3<f64>, Matrix3<f64>) {
+    let rows = matrix
+        .row_iter()
+        .map(|row| Vector3::new(row[0], row[1], row[2]))
+        .collect::<Vec<_>>();
+    compute_pca3_with_options(&rows, &Vector3::zeros(), &CovarianceOptions::default())
+}
+
+// Same as compute_pca3_with_eigenvalues on a slice of rows with a configurable covariance
+// estimate, center is the query point the distance weights are computed from. Only the
+// robust and weighted estimates allocate.
+pub fn compute_pca3_with_options<T: Real>(
+    rows: &[Vector3<T>],
+    center: &Vector3<T>,
+    options: &CovarianceOptions,
+) -> (Vector3<T>, Matrix3<T>) {
+    let covariance_matrix = match options {
+        CovarianceOptions {
+            weighting: CovarianceWeighting::Uniform,
+            estimator: CovarianceEstimator::Standard,
+            unbiased,
+        } => compute_covariance_matrix3(rows, *unbiased),
+        _ => compute_robust_covariance_matrix3(rows, center, options),
+    };
+    compute_eigen3(covariance_matrix)
+}
+
+pub fn compute_pca(matrix: &DMatrix<f64>) -> DMatrix<f64> {
+    let eigenvectors = compute_pca3(matrix);
+    DMatrix::from_column_slice(3, 3, eigenvectors.as_slice())
+}
+
+// Classifies a neighborhood from its sorted eigenvalues, comparing the largest one to the
+// largest absolute coordinate so that round-off of duplicated points counts as zero spread
+pub fn classify_neighborhood<T: Real>(
+    eigenvalues: &Vector3<T>,
+    rows: &[Vector3<T>],
+) -> NeighborhoodClass {
+    // The tolerance is loosened for precisions whose round-off exceeds it
+    let tolerance = T::cast(RANK_TOLERANCE).max(T::default_epsilon() * T::cast(ROUND_OFF_EPSILONS));
+    let magnitude = rows.iter().fold(T::zero(), |acc, row| acc.max(row.amax()));
+    let largest = eigenvalues[0];
+    if largest.is_nan() || largest <= (tolerance * magnitude).powi(2) {
+        NeighborhoodClass::Degenerate
+    } else if eigenvalues[1] <= tolerance * largest {
+        NeighborhoodClass::Linear
+    } else if eigenvalues[2] <= tolerance * largest {
+        NeighborhoodClass::Planar
+    } else {
+        NeighborhoodClass::FullRank
+    }
 }
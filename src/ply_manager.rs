@@ -10,7 +10,7 @@ fn extract_value(property: &Property) -> f64 {
         Property::Int(value) => value as f64,
         Property::UInt(value) => value as f64,
         Property::Float(value) => value as f64,
-        Property::Double(value) => value as f64,
+        Property::Double(value) => value,
         _ => panic!("extract_value: Unexpected value found in property."),
     }
 }
@@ -57,7 +57,7 @@ pub fn read_point_cloud(path: &str) -> (Vec<[f64; 3]>, Vec<[u8; 3]>) {
         .get("vertex")
         .expect("Vertex element not found.");
     let (points, colors) = parser
-        .read_payload_for_element(&mut reader, &element, &header)
+        .read_payload_for_element(&mut reader, element, &header)
         .expect("Failure when reading ply payload.")
         .into_iter()
         .map(|Vertex { xyz, rgb }| (xyz, rgb))
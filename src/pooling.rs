@@ -1,19 +1,28 @@
+use crate::precision::Real;
 use na::{DMatrix, Matrix1xX};
+use rayon::prelude::*;
+
+// Compression of the t-digests behind the approximate quantiles
+pub const DEFAULT_COMPRESSION: f64 = 100.;
+// Rows handed to each rayon task by accumulate_parallel
+const PARALLEL_CHUNK_ROWS: usize = 4096;
 
 enum PoolingTechnique {
-    MeanPooling,
-    MaxPooling,
-    MinPooling,
-    MedianPooling,
+    Mean,
+    Max,
+    Min,
+    Median,
+    Variance,
 }
 
 impl PoolingTechnique {
     fn from_str(pooling: &str) -> Option<Self> {
         match pooling {
-            "mean_pooling" => Some(Self::MeanPooling),
-            "max_pooling" => Some(Self::MaxPooling),
-            "min_pooling" => Some(Self::MinPooling),
-            "median_pooling" => Some(Self::MedianPooling),
+            "mean_pooling" => Some(Self::Mean),
+            "max_pooling" => Some(Self::Max),
+            "min_pooling" => Some(Self::Min),
+            "median_pooling" => Some(Self::Median),
+            "variance_pooling" => Some(Self::Variance),
             _ => None,
         }
     }
@@ -28,34 +37,51 @@ impl Pool {
         PoolingTechnique::from_str(pooling).map(|technique| Self { technique })
     }
 
-    pub fn pool<'a>(&self, matrix: &'a DMatrix<f64>) -> Matrix1xX<f64> {
+    pub fn pool(&self, matrix: &DMatrix<f64>) -> Matrix1xX<f64> {
         match self.technique {
-            PoolingTechnique::MeanPooling => self.mean_pooling(matrix),
-            PoolingTechnique::MaxPooling => self.max_pooling(matrix),
-            PoolingTechnique::MinPooling => self.min_pooling(matrix),
-            PoolingTechnique::MedianPooling => self.median_pooling(matrix),
+            PoolingTechnique::Mean => self.mean_pooling(matrix),
+            PoolingTechnique::Max => self.max_pooling(matrix),
+            PoolingTechnique::Min => self.min_pooling(matrix),
+            PoolingTechnique::Median => self.median_pooling(matrix),
+            PoolingTechnique::Variance => self.variance_pooling(matrix),
         }
     }
 
-    fn mean_pooling<'a>(&self, matrix: &'a DMatrix<f64>) -> Matrix1xX<f64> {
+    // Incremental equivalent of pool for rows of ncols columns. Median pooling becomes
+    // approximate, see QuantileAccumulator.
+    pub fn accumulator(&self, ncols: usize) -> PoolAccumulator {
+        match self.technique {
+            PoolingTechnique::Mean => PoolAccumulator::Mean(MeanAccumulator::new(ncols)),
+            PoolingTechnique::Max => PoolAccumulator::Max(MaxAccumulator::new(ncols)),
+            PoolingTechnique::Min => PoolAccumulator::Min(MinAccumulator::new(ncols)),
+            PoolingTechnique::Median => {
+                PoolAccumulator::Median(QuantileAccumulator::new(ncols, &[0.5]))
+            }
+            PoolingTechnique::Variance => {
+                PoolAccumulator::Variance(VarianceAccumulator::new(ncols))
+            }
+        }
+    }
+
+    fn mean_pooling(&self, matrix: &DMatrix<f64>) -> Matrix1xX<f64> {
         matrix.row_mean()
     }
 
-    fn max_pooling<'a>(&self, matrix: &'a DMatrix<f64>) -> Matrix1xX<f64> {
+    fn max_pooling(&self, matrix: &DMatrix<f64>) -> Matrix1xX<f64> {
         (0..matrix.ncols())
             .map(|i| matrix.column(i).max())
             .collect::<Vec<_>>()
             .into()
     }
 
-    fn min_pooling<'a>(&self, matrix: &'a DMatrix<f64>) -> Matrix1xX<f64> {
+    fn min_pooling(&self, matrix: &DMatrix<f64>) -> Matrix1xX<f64> {
         (0..matrix.ncols())
             .map(|i| matrix.column(i).min())
             .collect::<Vec<_>>()
             .into()
     }
 
-    fn median_pooling<'a>(&self, matrix: &'a DMatrix<f64>) -> Matrix1xX<f64> {
+    fn median_pooling(&self, matrix: &DMatrix<f64>) -> Matrix1xX<f64> {
         let ncols = matrix.ncols();
         let mut medians = Matrix1xX::zeros(ncols);
         for i in 0..ncols {
@@ -71,4 +97,434 @@ impl Pool {
         }
         medians
     }
+
+    fn variance_pooling(&self, matrix: &DMatrix<f64>) -> Matrix1xX<f64> {
+        matrix.row_variance()
+    }
+}
+
+// Pooling state updated one row at a time. Accumulators over disjoint rows can be merged,
+// so rows may be pooled in chunks, across rayon threads or across machines.
+pub trait Accumulator: Send + Sized {
+    fn push(&mut self, row: &[f64]);
+
+    fn merge(&mut self, other: Self);
+
+    fn finish(&self) -> Matrix1xX<f64>;
+
+    fn push_rows(&mut self, rows: &DMatrix<f64>) {
+        let mut row = vec![0.; rows.ncols()];
+        for i in 0..rows.nrows() {
+            row.iter_mut()
+                .zip(rows.row(i).iter())
+                .for_each(|(x, &y)| *x = y);
+            self.push(&row);
+        }
+    }
+}
+
+// Pools the rows in parallel, starting every rayon task from empty() and merging the results
+pub fn accumulate_parallel<A: Accumulator>(rows: &DMatrix<f64>, empty: impl Fn() -> A + Sync) -> A {
+    let nrows = rows.nrows();
+    (0..nrows.div_ceil(PARALLEL_CHUNK_ROWS))
+        .into_par_iter()
+        .map(|chunk| {
+            let start = chunk * PARALLEL_CHUNK_ROWS;
+            let len = PARALLEL_CHUNK_ROWS.min(nrows - start);
+            let mut accumulator = empty();
+            accumulator.push_rows(&rows.rows(start, len).into_owned());
+            accumulator
+        })
+        .reduce(&empty, |mut a, b| {
+            a.merge(b);
+            a
+        })
+}
+
+// Running column means. Rows pushed sequentially give the same result as mean pooling of
+// the stacked rows.
+#[derive(Clone, Debug)]
+pub struct MeanAccumulator {
+    sums: Matrix1xX<f64>,
+    count: usize,
+}
+
+impl MeanAccumulator {
+    pub fn new(ncols: usize) -> Self {
+        Self {
+            sums: Matrix1xX::zeros(ncols),
+            count: 0,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    // push_rows for per-point values in any precision, summed in f64
+    pub fn push_real_rows<T: Real>(&mut self, rows: &DMatrix<T>) {
+        for (j, column) in rows.column_iter().enumerate() {
+            self.sums[j] = column.iter().fold(self.sums[j], |a, &b| a + b.to_f64());
+        }
+        self.count += rows.nrows();
+    }
+}
+
+impl Accumulator for MeanAccumulator {
+    fn push(&mut self, row: &[f64]) {
+        self.sums
+            .iter_mut()
+            .zip(row)
+            .for_each(|(sum, &x)| *sum += x);
+        self.count += 1;
+    }
+
+    fn merge(&mut self, other: Self) {
+        self.sums += other.sums;
+        self.count += other.count;
+    }
+
+    fn finish(&self) -> Matrix1xX<f64> {
+        &self.sums / self.count as f64
+    }
+
+    // Column by column, keeping the summation order of mean_pooling
+    fn push_rows(&mut self, rows: &DMatrix<f64>) {
+        self.push_real_rows(rows);
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MaxAccumulator {
+    maxima: Matrix1xX<f64>,
+}
+
+impl MaxAccumulator {
+    pub fn new(ncols: usize) -> Self {
+        Self {
+            maxima: Matrix1xX::from_element(ncols, f64::NEG_INFINITY),
+        }
+    }
+}
+
+impl Accumulator for MaxAccumulator {
+    fn push(&mut self, row: &[f64]) {
+        self.maxima
+            .iter_mut()
+            .zip(row)
+            .for_each(|(max, &x)| *max = max.max(x));
+    }
+
+    fn merge(&mut self, other: Self) {
+        self.push(other.maxima.as_slice());
+    }
+
+    fn finish(&self) -> Matrix1xX<f64> {
+        self.maxima.clone()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MinAccumulator {
+    minima: Matrix1xX<f64>,
+}
+
+impl MinAccumulator {
+    pub fn new(ncols: usize) -> Self {
+        Self {
+            minima: Matrix1xX::from_element(ncols, f64::INFINITY),
+        }
+    }
+}
+
+impl Accumulator for MinAccumulator {
+    fn push(&mut self, row: &[f64]) {
+        self.minima
+            .iter_mut()
+            .zip(row)
+            .for_each(|(min, &x)| *min = min.min(x));
+    }
+
+    fn merge(&mut self, other: Self) {
+        self.push(other.minima.as_slice());
+    }
+
+    fn finish(&self) -> Matrix1xX<f64> {
+        self.minima.clone()
+    }
+}
+
+// Population variance of every column, using Welford updates and the pairwise merge of
+// Chan et al. so that it stays accurate for large means
+#[derive(Clone, Debug)]
+pub struct VarianceAccumulator {
+    count: usize,
+    means: Matrix1xX<f64>,
+    squared_deviations: Matrix1xX<f64>,
+}
+
+impl VarianceAccumulator {
+    pub fn new(ncols: usize) -> Self {
+        Self {
+            count: 0,
+            means: Matrix1xX::zeros(ncols),
+            squared_deviations: Matrix1xX::zeros(ncols),
+        }
+    }
+
+    pub fn means(&self) -> &Matrix1xX<f64> {
+        &self.means
+    }
+}
+
+impl Accumulator for VarianceAccumulator {
+    fn push(&mut self, row: &[f64]) {
+        self.count += 1;
+        let count = self.count as f64;
+        for (j, &x) in row.iter().enumerate() {
+            let delta = x - self.means[j];
+            self.means[j] += delta / count;
+            self.squared_deviations[j] += delta * (x - self.means[j]);
+        }
+    }
+
+    fn merge(&mut self, other: Self) {
+        if other.count == 0 {
+            return;
+        }
+        if self.count == 0 {
+            *self = other;
+            return;
+        }
+        let (n_a, n_b) = (self.count as f64, other.count as f64);
+        let count = n_a + n_b;
+        for j in 0..self.means.ncols() {
+            let delta = other.means[j] - self.means[j];
+            self.means[j] += delta * n_b / count;
+            self.squared_deviations[j] +=
+                other.squared_deviations[j] + delta * delta * n_a * n_b / count;
+        }
+        self.count += other.count;
+    }
+
+    fn finish(&self) -> Matrix1xX<f64> {
+        &self.squared_deviations / self.count as f64
+    }
+}
+
+// Merging t-digest (Dunning and Ertl) of a single column. Values are buffered and merged
+// into centroids whose size is bounded by the arcsine scale function, so that centroids
+// near the extremes stay small. NaN values are ignored.
+#[derive(Clone, Debug)]
+pub struct TDigest {
+    compression: f64,
+    // (mean, weight) sorted by mean
+    centroids: Vec<(f64, f64)>,
+    buffer: Vec<f64>,
+    min: f64,
+    max: f64,
+}
+
+impl TDigest {
+    pub fn new(compression: f64) -> Self {
+        Self {
+            compression,
+            centroids: Vec::new(),
+            buffer: Vec::new(),
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+
+    pub fn push(&mut self, x: f64) {
+        if x.is_nan() {
+            return;
+        }
+        self.min = self.min.min(x);
+        self.max = self.max.max(x);
+        self.buffer.push(x);
+        if self.buffer.len() as f64 >= 5. * self.compression {
+            self.compress();
+        }
+    }
+
+    pub fn merge(&mut self, mut other: Self) {
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.buffer.append(&mut other.buffer);
+        self.centroids.append(&mut other.centroids);
+        self.compress();
+    }
+
+    pub fn count(&self) -> f64 {
+        self.centroids.iter().map(|c| c.1).sum::<f64>() + self.buffer.len() as f64
+    }
+
+    // Position on the arcsine scale, centroids may span at most one unit of it
+    fn scale(&self, q: f64) -> f64 {
+        self.compression * (2. * q.clamp(0., 1.) - 1.).asin() / (2. * std::f64::consts::PI)
+    }
+
+    fn compress(&mut self) {
+        let mut points = std::mem::take(&mut self.centroids);
+        points.extend(self.buffer.drain(..).map(|x| (x, 1.)));
+        if points.is_empty() {
+            return;
+        }
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let total = points.iter().map(|c| c.1).sum::<f64>();
+        let mut merged = Vec::with_capacity(points.len().min(self.compression as usize * 2));
+        let mut current = points[0];
+        let mut cumulative = 0.;
+        let mut limit = self.scale(0.) + 1.;
+        for &(mean, weight) in &points[1..] {
+            let q = (cumulative + current.1 + weight) / total;
+            if self.scale(q) <= limit {
+                current.1 += weight;
+                current.0 += (mean - current.0) * weight / current.1;
+            } else {
+                cumulative += current.1;
+                merged.push(current);
+                limit = self.scale(cumulative / total) + 1.;
+                current = (mean, weight);
+            }
+        }
+        merged.push(current);
+        self.centroids = merged;
+    }
+
+    // Approximate q-quantile, interpolating between centroid centers and the exact extremes
+    pub fn quantile(&mut self, q: f64) -> f64 {
+        self.compress();
+        let total = self.count();
+        if total == 0. {
+            return f64::NAN;
+        }
+        let target = q.clamp(0., 1.) * total;
+        let (mut previous_position, mut previous_value) = (0., self.min);
+        let mut cumulative = 0.;
+        for &(mean, weight) in &self.centroids {
+            let position = cumulative + weight / 2.;
+            if target < position {
+                let t = (target - previous_position) / (position - previous_position);
+                return previous_value + t * (mean - previous_value);
+            }
+            (previous_position, previous_value) = (position, mean);
+            cumulative += weight;
+        }
+        if total > previous_position {
+            let t = (target - previous_position) / (total - previous_position);
+            previous_value + t.min(1.) * (self.max - previous_value)
+        } else {
+            self.max
+        }
+    }
+}
+
+// Approximate quantiles of every column from one t-digest per column. With the default
+// compression of 100 the rank of an estimate is within 0.3% of the requested quantile
+// (checked in tests/pooling.rs), whether rows are pushed sequentially or merged across
+// threads. finish returns, for each requested quantile in order, the quantiles of all
+// columns.
+#[derive(Clone, Debug)]
+pub struct QuantileAccumulator {
+    quantiles: Vec<f64>,
+    digests: Vec<TDigest>,
+}
+
+impl QuantileAccumulator {
+    pub fn new(ncols: usize, quantiles: &[f64]) -> Self {
+        Self::with_compression(ncols, quantiles, DEFAULT_COMPRESSION)
+    }
+
+    pub fn with_compression(ncols: usize, quantiles: &[f64], compression: f64) -> Self {
+        Self {
+            quantiles: quantiles.to_vec(),
+            digests: vec![TDigest::new(compression); ncols],
+        }
+    }
+}
+
+impl Accumulator for QuantileAccumulator {
+    fn push(&mut self, row: &[f64]) {
+        self.digests
+            .iter_mut()
+            .zip(row)
+            .for_each(|(digest, &x)| digest.push(x));
+    }
+
+    fn merge(&mut self, other: Self) {
+        self.digests
+            .iter_mut()
+            .zip(other.digests)
+            .for_each(|(digest, other)| digest.merge(other));
+    }
+
+    fn finish(&self) -> Matrix1xX<f64> {
+        let mut digests = self.digests.clone();
+        let values = self
+            .quantiles
+            .iter()
+            .flat_map(|&q| {
+                digests
+                    .iter_mut()
+                    .map(|d| d.quantile(q))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        Matrix1xX::from_vec(values)
+    }
+}
+
+// Accumulator matching a Pool technique
+#[derive(Clone, Debug)]
+pub enum PoolAccumulator {
+    Mean(MeanAccumulator),
+    Max(MaxAccumulator),
+    Min(MinAccumulator),
+    Median(QuantileAccumulator),
+    Variance(VarianceAccumulator),
+}
+
+impl Accumulator for PoolAccumulator {
+    fn push(&mut self, row: &[f64]) {
+        match self {
+            Self::Mean(a) => a.push(row),
+            Self::Max(a) => a.push(row),
+            Self::Min(a) => a.push(row),
+            Self::Median(a) => a.push(row),
+            Self::Variance(a) => a.push(row),
+        }
+    }
+
+    fn merge(&mut self, other: Self) {
+        match (self, other) {
+            (Self::Mean(a), Self::Mean(b)) => a.merge(b),
+            (Self::Max(a), Self::Max(b)) => a.merge(b),
+            (Self::Min(a), Self::Min(b)) => a.merge(b),
+            (Self::Median(a), Self::Median(b)) => a.merge(b),
+            (Self::Variance(a), Self::Variance(b)) => a.merge(b),
+            _ => panic!("Cannot merge accumulators of different pooling techniques."),
+        }
+    }
+
+    fn finish(&self) -> Matrix1xX<f64> {
+        match self {
+            Self::Mean(a) => a.finish(),
+            Self::Max(a) => a.finish(),
+            Self::Min(a) => a.finish(),
+            Self::Median(a) => a.finish(),
+            Self::Variance(a) => a.finish(),
+        }
+    }
+
+    fn push_rows(&mut self, rows: &DMatrix<f64>) {
+        match self {
+            Self::Mean(a) => a.push_rows(rows),
+            Self::Max(a) => a.push_rows(rows),
+            Self::Min(a) => a.push_rows(rows),
+            Self::Median(a) => a.push_rows(rows),
+            Self::Variance(a) => a.push_rows(rows),
+        }
+    }
 }
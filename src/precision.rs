@@ -0,0 +1,117 @@
+use crate::predictors::{LocalFeatures, Predictor};
+use na::{DMatrix, RealField};
+use std::cmp::Ordering;
+
+// Floating-point type of the local PCA, the local features and the per-point predictors.
+// Preprocessing, neighbor search and pooling always run in f64
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Precision {
+    #[default]
+    F64,
+    // Halves the memory of the local features and per-point predictors
+    F32,
+}
+
+impl Precision {
+    pub fn scalar_size(&self) -> usize {
+        match self {
+            Precision::F64 => std::mem::size_of::<f64>(),
+            Precision::F32 => std::mem::size_of::<f32>(),
+        }
+    }
+}
+
+// Scalar types the feature kernel and the predictors are computed in
+pub trait Real: RealField + Copy {
+    const PRECISION: Precision;
+
+    fn cast(x: f64) -> Self;
+
+    fn to_f64(self) -> f64;
+
+    // Coordinate relative to origin. Reduced precisions subtract the origin (the query
+    // point) before rounding, so that large absolute coordinates keep their local detail
+    fn from_coordinate(x: f64, origin: f64) -> Self;
+
+    fn total_cmp(&self, other: &Self) -> Ordering;
+
+    fn is_nan(self) -> bool;
+
+    // Arc cosine of the libm crate, which the f64 predictors have always used
+    fn acos_libm(self) -> Self;
+
+    fn compute_predictor(
+        predictor: &dyn Predictor,
+        local_features: &LocalFeatures<Self>,
+    ) -> DMatrix<Self>;
+}
+
+impl Real for f64 {
+    const PRECISION: Precision = Precision::F64;
+
+    fn cast(x: f64) -> Self {
+        x
+    }
+
+    fn to_f64(self) -> f64 {
+        self
+    }
+
+    fn from_coordinate(x: f64, _origin: f64) -> Self {
+        x
+    }
+
+    fn total_cmp(&self, other: &Self) -> Ordering {
+        f64::total_cmp(self, other)
+    }
+
+    fn is_nan(self) -> bool {
+        f64::is_nan(self)
+    }
+
+    fn acos_libm(self) -> Self {
+        libm::acos(self)
+    }
+
+    fn compute_predictor(
+        predictor: &dyn Predictor,
+        local_features: &LocalFeatures<Self>,
+    ) -> DMatrix<Self> {
+        predictor.compute(local_features)
+    }
+}
+
+impl Real for f32 {
+    const PRECISION: Precision = Precision::F32;
+
+    fn cast(x: f64) -> Self {
+        x as f32
+    }
+
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+
+    fn from_coordinate(x: f64, origin: f64) -> Self {
+        (x - origin) as f32
+    }
+
+    fn total_cmp(&self, other: &Self) -> Ordering {
+        f32::total_cmp(self, other)
+    }
+
+    fn is_nan(self) -> bool {
+        f32::is_nan(self)
+    }
+
+    fn acos_libm(self) -> Self {
+        libm::acosf(self)
+    }
+
+    fn compute_predictor(
+        predictor: &dyn Predictor,
+        local_features: &LocalFeatures<Self>,
+    ) -> DMatrix<Self> {
+        predictor.compute_f32(local_features)
+    }
+}
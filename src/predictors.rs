@@ -1,226 +1,699 @@
+use crate::error::PointPca2Error;
 use crate::pooling;
+use crate::precision::Real;
 use crate::spatial_metrics;
-use na::{DMatrix, Matrix1xX};
+use na::{DMatrix, Dyn, Matrix1xX, MatrixView, Scalar};
 use rayon::prelude::*;
+use std::fmt;
+use std::sync::Arc;
 
-const PREDICTORS_DIMENSION: usize = 40;
+// Width of the built-in predictors
+pub const PREDICTORS_DIMENSION: usize = 40;
+
+// Attributes of the clouds that are compared
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Attributes {
+    #[default]
+    Both,
+    // Points only, colors are ignored
+    Geometry,
+    // Colors of the neighborhoods only, the distorted geometry is ignored
+    Texture,
+}
+
+impl Attributes {
+    pub fn includes_geometry(&self) -> bool {
+        *self != Attributes::Texture
+    }
+
+    pub fn includes_texture(&self) -> bool {
+        *self != Attributes::Geometry
+    }
+}
+
+pub type Columns<'a, T = f64> = MatrixView<'a, T, Dyn, Dyn>;
+
+// Named column groups of the local features, one row per point. Geometry is expressed in
+// the PCA basis of the reference neighborhood, centered on its mean
+#[derive(Clone, Copy, Debug)]
+pub struct LocalFeatures<'a, T: Scalar = f64> {
+    matrix: &'a DMatrix<T>,
+}
+
+impl<'a, T: Scalar> LocalFeatures<'a, T> {
+    pub fn new(matrix: &'a DMatrix<T>) -> Self {
+        Self { matrix }
+    }
+
+    pub fn matrix(&self) -> &'a DMatrix<T> {
+        self.matrix
+    }
+
+    pub fn nrows(&self) -> usize {
+        self.matrix.nrows()
+    }
+
+    pub fn projection_a_to_a(&self) -> Columns<'a, T> {
+        self.matrix.columns(0, 3)
+    }
+
+    pub fn projection_b_to_a(&self) -> Columns<'a, T> {
+        self.matrix.columns(3, 3)
+    }
+
+    pub fn colors_mean_a(&self) -> Columns<'a, T> {
+        self.matrix.columns(6, 3)
+    }
+
+    pub fn points_mean_b(&self) -> Columns<'a, T> {
+        self.matrix.columns(9, 3)
+    }
+
+    pub fn colors_mean_b(&self) -> Columns<'a, T> {
+        self.matrix.columns(12, 3)
+    }
+
+    pub fn points_variance_a(&self) -> Columns<'a, T> {
+        self.matrix.columns(15, 3)
+    }
+
+    pub fn colors_variance_a(&self) -> Columns<'a, T> {
+        self.matrix.columns(18, 3)
+    }
+
+    pub fn points_variance_b(&self) -> Columns<'a, T> {
+        self.matrix.columns(21, 3)
+    }
+
+    pub fn colors_variance_b(&self) -> Columns<'a, T> {
+        self.matrix.columns(24, 3)
+    }
+
+    pub fn points_covariance_ab(&self) -> Columns<'a, T> {
+        self.matrix.columns(27, 3)
+    }
+
+    pub fn colors_covariance_ab(&self) -> Columns<'a, T> {
+        self.matrix.columns(30, 3)
+    }
+
+    // Principal axes of the projected distorted neighborhood, in the reference basis
+    pub fn eigenvectors_b_x(&self) -> Columns<'a, T> {
+        self.matrix.columns(33, 3)
+    }
+
+    pub fn eigenvectors_b_y(&self) -> Columns<'a, T> {
+        self.matrix.columns(36, 3)
+    }
+
+    pub fn eigenvectors_b_z(&self) -> Columns<'a, T> {
+        self.matrix.columns(39, 3)
+    }
+}
+
+// A per-point predictor computed from the local features
+pub trait Predictor: fmt::Debug + Send + Sync {
+    // Unique within a registry
+    fn name(&self) -> &str;
+
+    // Number of output columns
+    fn width(&self) -> usize;
+
+    // Attributes of the local features read by compute
+    fn attributes(&self) -> Attributes {
+        Attributes::Both
+    }
+
+    // nrows x width values, one row per point
+    fn compute(&self, local_features: &LocalFeatures) -> DMatrix<f64>;
+
+    // Same as compute in the f32 precision, by default through compute on f64 copies
+    fn compute_f32(&self, local_features: &LocalFeatures<f32>) -> DMatrix<f32> {
+        let matrix = local_features.matrix().map(|x| x as f64);
+        self.compute(&LocalFeatures::new(&matrix)).map(|x| x as f32)
+    }
+
+    // Label of each output column
+    fn column_names(&self) -> Vec<String> {
+        match self.width() {
+            1 => vec![self.name().to_string()],
+            width => (0..width)
+                .map(|j| format!("{}_{}", self.name(), j))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Builtin<T: Real> {
+    name: &'static str,
+    // Suffixes of the output columns, a single column when empty
+    suffixes: &'static [&'static str],
+    attributes: Attributes,
+    compute: fn(&LocalFeatures<T>) -> DMatrix<T>,
+}
+
+// A built-in predictor, with its formula instantiated in both precisions
+#[derive(Clone, Copy)]
+struct BuiltinPredictor {
+    f64: Builtin<f64>,
+    f32: Builtin<f32>,
+}
+
+impl BuiltinPredictor {
+    fn pair(f64s: Vec<Builtin<f64>>, f32s: Vec<Builtin<f32>>) -> Vec<Self> {
+        f64s.into_iter()
+            .zip(f32s)
+            .map(|(f64, f32)| Self { f64, f32 })
+            .collect()
+    }
+}
+
+impl fmt::Debug for BuiltinPredictor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Builtin({})", self.f64.name)
+    }
+}
+
+impl Predictor for BuiltinPredictor {
+    fn name(&self) -> &str {
+        self.f64.name
+    }
+
+    fn width(&self) -> usize {
+        self.f64.suffixes.len().max(1)
+    }
+
+    fn attributes(&self) -> Attributes {
+        self.f64.attributes
+    }
+
+    fn compute(&self, local_features: &LocalFeatures) -> DMatrix<f64> {
+        (self.f64.compute)(local_features)
+    }
+
+    fn compute_f32(&self, local_features: &LocalFeatures<f32>) -> DMatrix<f32> {
+        (self.f32.compute)(local_features)
+    }
+
+    fn column_names(&self) -> Vec<String> {
+        if self.f64.suffixes.is_empty() {
+            return vec![self.f64.name.to_string()];
+        }
+        self.f64
+            .suffixes
+            .iter()
+            .map(|suffix| format!("{}_{}", self.f64.name, suffix))
+            .collect()
+    }
+}
+
+const CHANNELS: &[&str] = &["y", "u", "v"];
+const AXES: &[&str] = &["e1", "e2", "e3"];
+const MINOR_AXES: &[&str] = &["e2", "e3"];
+
+// The PointPCA2 predictors, textural ones first
+fn builtins<T: Real>() -> Vec<Builtin<T>> {
+    vec![
+        Builtin {
+            name: "color_mean",
+            attributes: Attributes::Texture,
+            suffixes: CHANNELS,
+            compute: |f| {
+                spatial_metrics::iter_relative_difference(&f.colors_mean_a(), &f.colors_mean_b())
+            },
+        },
+        Builtin {
+            name: "color_variance",
+            attributes: Attributes::Texture,
+            suffixes: CHANNELS,
+            compute: |f| {
+                spatial_metrics::iter_relative_difference(
+                    &f.colors_variance_a(),
+                    &f.colors_variance_b(),
+                )
+            },
+        },
+        Builtin {
+            name: "color_covariance",
+            attributes: Attributes::Texture,
+            suffixes: CHANNELS,
+            compute: |f| {
+                spatial_metrics::covariance_differences(
+                    &f.colors_variance_a(),
+                    &f.colors_variance_b(),
+                    &f.colors_covariance_ab(),
+                )
+            },
+        },
+        Builtin {
+            name: "color_variance_sum",
+            attributes: Attributes::Texture,
+            suffixes: &[],
+            compute: |f| {
+                spatial_metrics::textural_variance_sum(
+                    &f.colors_variance_a(),
+                    &f.colors_variance_b(),
+                )
+            },
+        },
+        Builtin {
+            name: "color_omnivariance",
+            attributes: Attributes::Texture,
+            suffixes: &[],
+            compute: |f| {
+                spatial_metrics::omnivariance_differences(
+                    &f.colors_variance_a(),
+                    &f.colors_variance_b(),
+                )
+            },
+        },
+        Builtin {
+            name: "color_entropy",
+            attributes: Attributes::Texture,
+            suffixes: &[],
+            compute: |f| spatial_metrics::entropy(&f.colors_variance_a(), &f.colors_variance_b()),
+        },
+        Builtin {
+            name: "point_distance",
+            attributes: Attributes::Geometry,
+            suffixes: &[],
+            compute: |f| {
+                spatial_metrics::euclidean_distances(&f.projection_a_to_a(), &f.projection_b_to_a())
+            },
+        },
+        Builtin {
+            name: "axis_distance_e1",
+            attributes: Attributes::Geometry,
+            suffixes: &[],
+            compute: |f| {
+                spatial_metrics::vector_projected_distances(
+                    &f.projection_a_to_a(),
+                    &f.projection_b_to_a(),
+                    0,
+                )
+            },
+        },
+        Builtin {
+            name: "axis_distance_e2",
+            attributes: Attributes::Geometry,
+            suffixes: &[],
+            compute: |f| {
+                spatial_metrics::vector_projected_distances(
+                    &f.projection_a_to_a(),
+                    &f.projection_b_to_a(),
+                    1,
+                )
+            },
+        },
+        Builtin {
+            name: "axis_distance_e3",
+            attributes: Attributes::Geometry,
+            suffixes: &[],
+            compute: |f| {
+                spatial_metrics::vector_projected_distances(
+                    &f.projection_a_to_a(),
+                    &f.projection_b_to_a(),
+                    2,
+                )
+            },
+        },
+        Builtin {
+            name: "reference_offset",
+            attributes: Attributes::Geometry,
+            suffixes: MINOR_AXES,
+            compute: |f| spatial_metrics::point_projected_distances(&f.projection_a_to_a()),
+        },
+        Builtin {
+            name: "distorted_to_centroid",
+            attributes: Attributes::Geometry,
+            suffixes: &[],
+            compute: |f| spatial_metrics::point_to_centroid_distances(&f.projection_b_to_a()),
+        },
+        Builtin {
+            name: "distorted_offset",
+            attributes: Attributes::Geometry,
+            suffixes: MINOR_AXES,
+            compute: |f| spatial_metrics::point_projected_distances(&f.projection_b_to_a()),
+        },
+        Builtin {
+            name: "centroid_distance",
+            attributes: Attributes::Geometry,
+            suffixes: &[],
+            compute: |f| spatial_metrics::point_to_centroid_distances(&f.points_mean_b()),
+        },
+        Builtin {
+            name: "centroid_offset",
+            attributes: Attributes::Geometry,
+            suffixes: MINOR_AXES,
+            compute: |f| spatial_metrics::point_projected_distances(&f.points_mean_b()),
+        },
+        Builtin {
+            name: "point_variance",
+            attributes: Attributes::Geometry,
+            suffixes: AXES,
+            compute: |f| {
+                spatial_metrics::iter_relative_difference(
+                    &f.points_variance_a(),
+                    &f.points_variance_b(),
+                )
+            },
+        },
+        Builtin {
+            name: "point_covariance",
+            attributes: Attributes::Geometry,
+            suffixes: AXES,
+            compute: |f| {
+                spatial_metrics::covariance_differences(
+                    &f.points_variance_a(),
+                    &f.points_variance_b(),
+                    &f.points_covariance_ab(),
+                )
+            },
+        },
+        Builtin {
+            name: "omnivariance",
+            attributes: Attributes::Geometry,
+            suffixes: &[],
+            compute: |f| {
+                spatial_metrics::omnivariance_differences(
+                    &f.points_variance_a(),
+                    &f.points_variance_b(),
+                )
+            },
+        },
+        Builtin {
+            name: "entropy",
+            attributes: Attributes::Geometry,
+            suffixes: &[],
+            compute: |f| spatial_metrics::entropy(&f.points_variance_a(), &f.points_variance_b()),
+        },
+        Builtin {
+            name: "anisotropy",
+            attributes: Attributes::Geometry,
+            suffixes: &[],
+            compute: |f| {
+                spatial_metrics::anisotropy_planarity_linearity(
+                    &f.points_variance_a(),
+                    &f.points_variance_b(),
+                    0,
+                    2,
+                )
+            },
+        },
+        Builtin {
+            name: "planarity",
+            attributes: Attributes::Geometry,
+            suffixes: &[],
+            compute: |f| {
+                spatial_metrics::anisotropy_planarity_linearity(
+                    &f.points_variance_a(),
+                    &f.points_variance_b(),
+                    1,
+                    2,
+                )
+            },
+        },
+        Builtin {
+            name: "linearity",
+            attributes: Attributes::Geometry,
+            suffixes: &[],
+            compute: |f| {
+                spatial_metrics::anisotropy_planarity_linearity(
+                    &f.points_variance_a(),
+                    &f.points_variance_b(),
+                    0,
+                    1,
+                )
+            },
+        },
+        Builtin {
+            name: "surface_variation",
+            attributes: Attributes::Geometry,
+            suffixes: &[],
+            compute: |f| {
+                spatial_metrics::surface_variation(&f.points_variance_a(), &f.points_variance_b())
+            },
+        },
+        Builtin {
+            name: "sphericity",
+            attributes: Attributes::Geometry,
+            suffixes: &[],
+            compute: |f| {
+                spatial_metrics::sphericity(&f.points_variance_a(), &f.points_variance_b())
+            },
+        },
+        Builtin {
+            name: "angular_similarity",
+            attributes: Attributes::Geometry,
+            suffixes: &[],
+            compute: |f| spatial_metrics::angular_similarity(&f.eigenvectors_b_y()),
+        },
+        Builtin {
+            name: "parallelity_e1",
+            attributes: Attributes::Geometry,
+            suffixes: &[],
+            compute: |f| spatial_metrics::parallelity(&f.eigenvectors_b_x(), 0),
+        },
+        Builtin {
+            name: "parallelity_e3",
+            attributes: Attributes::Geometry,
+            suffixes: &[],
+            compute: |f| spatial_metrics::parallelity(&f.eigenvectors_b_z(), 2),
+        },
+    ]
+}
+
+fn check_width(predictor: &dyn Predictor, ncols: usize) -> Result<(), PointPca2Error> {
+    if ncols != predictor.width() {
+        return Err(PointPca2Error::PredictorWidthMismatch {
+            name: predictor.name().to_string(),
+            width: predictor.width(),
+            columns: ncols,
+        });
+    }
+    Ok(())
+}
+
+// Optional group comparing the local surfaces through their normals. The reference normal is
+// the third axis of the reference PCA basis and the distorted normal the third eigenvector of
+// the projected distorted neighborhood. The reference plane goes through the reference mean,
+// the distorted neighbors are measured against it by their root mean square distance, compared
+// with that of the reference neighbors
+fn normal_predictors<T: Real>() -> Vec<Builtin<T>> {
+    vec![
+        Builtin {
+            name: "point_to_plane_reference",
+            attributes: Attributes::Geometry,
+            suffixes: &[],
+            compute: |f| {
+                spatial_metrics::plane_rms_differences(
+                    &f.points_variance_a(),
+                    &f.points_mean_b(),
+                    &f.points_variance_b(),
+                    2,
+                )
+            },
+        },
+        Builtin {
+            name: "point_to_plane_distorted",
+            attributes: Attributes::Geometry,
+            suffixes: &[],
+            compute: |f| {
+                spatial_metrics::point_to_plane_distances(
+                    &f.projection_a_to_a(),
+                    &f.projection_b_to_a(),
+                    &f.eigenvectors_b_z(),
+                )
+            },
+        },
+        Builtin {
+            name: "point_to_plane_symmetric",
+            attributes: Attributes::Geometry,
+            suffixes: &[],
+            compute: |f| {
+                let reference = spatial_metrics::plane_rms_differences(
+                    &f.points_variance_a(),
+                    &f.points_mean_b(),
+                    &f.points_variance_b(),
+                    2,
+                );
+                let distorted = spatial_metrics::point_to_plane_distances(
+                    &f.projection_a_to_a(),
+                    &f.projection_b_to_a(),
+                    &f.eigenvectors_b_z(),
+                );
+                reference.zip_map(&distorted, |a, b| a.max(b))
+            },
+        },
+        Builtin {
+            name: "normal_similarity",
+            attributes: Attributes::Geometry,
+            suffixes: &[],
+            compute: |f| spatial_metrics::normal_angle_similarity(&f.eigenvectors_b_z(), 2),
+        },
+    ]
+}
+
+// Ordered set of predictors, whose outputs are laid out side by side in registration order
+#[derive(Clone, Debug)]
+pub struct PredictorRegistry {
+    predictors: Vec<Arc<dyn Predictor>>,
+}
+
+impl PredictorRegistry {
+    pub fn new() -> Self {
+        Self {
+            predictors: Vec::new(),
+        }
+    }
+
+    pub fn builtin() -> Self {
+        let mut registry = Self::new();
+        for builtin in BuiltinPredictor::pair(builtins(), builtins()) {
+            registry.predictors.push(Arc::new(builtin));
+        }
+        registry
+    }
+
+    fn check_name(&self, name: &str) -> Result<(), PointPca2Error> {
+        if self.predictors.iter().any(|p| p.name() == name) {
+            return Err(PointPca2Error::DuplicatePredictor {
+                name: name.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    // Adds the point-to-plane distances and the normal angle similarity after the registered
+    // predictors. Nothing is added if one of their names is already registered
+    pub fn register_normal_predictors(&mut self) -> Result<&mut Self, PointPca2Error> {
+        let predictors = BuiltinPredictor::pair(normal_predictors(), normal_predictors());
+        for predictor in &predictors {
+            self.check_name(predictor.name())?;
+        }
+        for predictor in predictors {
+            self.predictors.push(Arc::new(predictor));
+        }
+        Ok(self)
+    }
+
+    pub fn register(
+        &mut self,
+        predictor: impl Predictor + 'static,
+    ) -> Result<&mut Self, PointPca2Error> {
+        self.register_arc(Arc::new(predictor))
+    }
+
+    pub fn register_arc(
+        &mut self,
+        predictor: Arc<dyn Predictor>,
+    ) -> Result<&mut Self, PointPca2Error> {
+        self.check_name(predictor.name())?;
+        self.predictors.push(predictor);
+        Ok(self)
+    }
+
+    // Registered predictors reading only the given attributes, all of them for Both
+    pub fn for_attributes(&self, attributes: Attributes) -> Self {
+        Self {
+            predictors: self
+                .predictors
+                .iter()
+                .filter(|p| attributes == Attributes::Both || p.attributes() == attributes)
+                .cloned()
+                .collect(),
+        }
+    }
+
+    pub fn predictors(&self) -> &[Arc<dyn Predictor>] {
+        &self.predictors
+    }
+
+    // Total number of output columns
+    pub fn dimension(&self) -> usize {
+        self.predictors.iter().map(|p| p.width()).sum()
+    }
+
+    pub fn column_names(&self) -> Vec<String> {
+        self.predictors
+            .iter()
+            .flat_map(|p| p.column_names())
+            .collect()
+    }
+
+    // First output column of each predictor
+    fn offsets(&self) -> Vec<usize> {
+        self.predictors
+            .iter()
+            .scan(0, |offset, p| {
+                let start = *offset;
+                *offset += p.width();
+                Some(start)
+            })
+            .collect()
+    }
+
+    // Predictors of every point, before pooling, in the precision of the local features
+    pub fn compute_point_predictors<T: Real>(
+        &self,
+        local_features: &DMatrix<T>,
+    ) -> Result<DMatrix<T>, PointPca2Error> {
+        let local_features = LocalFeatures::new(local_features);
+        let mut point_predictors = DMatrix::zeros(local_features.nrows(), self.dimension());
+        let results: Vec<DMatrix<T>> = self
+            .predictors
+            .par_iter()
+            .map(|p| T::compute_predictor(p.as_ref(), &local_features))
+            .collect();
+        for ((p, start_col), values) in self.predictors.iter().zip(self.offsets()).zip(results) {
+            check_width(p.as_ref(), values.ncols())?;
+            point_predictors
+                .columns_mut(start_col, p.width())
+                .copy_from(&values);
+        }
+        Ok(point_predictors)
+    }
+
+    pub fn compute_predictors(
+        &self,
+        local_features: DMatrix<f64>,
+    ) -> Result<Matrix1xX<f64>, PointPca2Error> {
+        let local_features = LocalFeatures::new(&local_features);
+        let mut predictors = Matrix1xX::zeros(self.dimension());
+        let pooling = pooling::Pool::new("mean_pooling").unwrap();
+        // Compute and pool all predictors in parallel
+        let results: Vec<Matrix1xX<f64>> = self
+            .predictors
+            .par_iter()
+            .map(|p| pooling.pool(&p.compute(&local_features)))
+            .collect();
+        // Copy results back to the predictors matrix
+        for ((p, start_col), values) in self.predictors.iter().zip(self.offsets()).zip(results) {
+            check_width(p.as_ref(), values.ncols())?;
+            predictors
+                .columns_mut(start_col, p.width())
+                .copy_from(&values);
+        }
+        Ok(predictors)
+    }
+}
+
+impl Default for PredictorRegistry {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+// Built-in predictors of every point, before pooling
+pub fn compute_point_predictors(local_features: &DMatrix<f64>) -> DMatrix<f64> {
+    PredictorRegistry::builtin()
+        .compute_point_predictors(local_features)
+        .expect("Built-in predictors return as many columns as their width.")
+}
 
 pub fn compute_predictors(local_features: DMatrix<f64>) -> Matrix1xX<f64> {
-    let projection_a_to_a = local_features.columns(0, 3);
-    let projection_b_to_a = local_features.columns(3, 3);
-    let colors_mean_a = local_features.columns(6, 3);
-    let points_mean_b = local_features.columns(9, 3);
-    let colors_mean_b = local_features.columns(12, 3);
-    let points_variance_a = local_features.columns(15, 3);
-    let colors_variance_a = local_features.columns(18, 3);
-    let points_variance_b = local_features.columns(21, 3);
-    let colors_variance_b = local_features.columns(24, 3);
-    let points_covariance_ab = local_features.columns(27, 3);
-    let colors_covariance_ab = local_features.columns(30, 3);
-    let eigenvectors_b_x = local_features.columns(33, 3);
-    let eigenvectors_b_y = local_features.columns(36, 3);
-    let eigenvectors_b_z = local_features.columns(39, 3);
-    let mut predictors = Matrix1xX::zeros(PREDICTORS_DIMENSION);
-    let pooling = pooling::Pool::new("mean_pooling").unwrap();
-    // Define all predictor computations as closures that return (start_col, num_cols, values)
-    let predictor_computations: Vec<Box<dyn Fn() -> (usize, usize, Matrix1xX<f64>) + Send + Sync>> = vec![
-        // Textural predictors
-        Box::new(|| {
-            let pooled = pooling.pool(&spatial_metrics::iter_relative_difference(
-                &colors_mean_a,
-                &colors_mean_b,
-            ));
-            (0, 3, pooled)
-        }),
-        Box::new(|| {
-            let pooled = pooling.pool(&spatial_metrics::iter_relative_difference(
-                &colors_variance_a,
-                &colors_variance_b,
-            ));
-            (3, 3, pooled)
-        }),
-        Box::new(|| {
-            let pooled = pooling.pool(&spatial_metrics::covariance_differences(
-                &colors_variance_a,
-                &colors_variance_b,
-                &colors_covariance_ab,
-            ));
-            (6, 3, pooled)
-        }),
-        Box::new(|| {
-            let pooled = pooling.pool(&spatial_metrics::textural_variance_sum(
-                &colors_variance_a,
-                &colors_variance_b,
-            ));
-            (9, 1, pooled)
-        }),
-        Box::new(|| {
-            let pooled = pooling.pool(&spatial_metrics::omnivariance_differences(
-                &colors_variance_a,
-                &colors_variance_b,
-            ));
-            (10, 1, pooled)
-        }),
-        Box::new(|| {
-            let pooled = pooling.pool(&spatial_metrics::entropy(
-                &colors_variance_a,
-                &colors_variance_b,
-            ));
-            (11, 1, pooled)
-        }),
-        // Geometric predictors
-        Box::new(|| {
-            let pooled = pooling.pool(&spatial_metrics::euclidean_distances(
-                &projection_a_to_a,
-                &projection_b_to_a,
-            ));
-            (12, 1, pooled)
-        }),
-        Box::new(|| {
-            let pooled = pooling.pool(&spatial_metrics::vector_projected_distances(
-                &projection_a_to_a,
-                &projection_b_to_a,
-                0,
-            ));
-            (13, 1, pooled)
-        }),
-        Box::new(|| {
-            let pooled = pooling.pool(&spatial_metrics::vector_projected_distances(
-                &projection_a_to_a,
-                &projection_b_to_a,
-                1,
-            ));
-            (14, 1, pooled)
-        }),
-        Box::new(|| {
-            let pooled = pooling.pool(&spatial_metrics::vector_projected_distances(
-                &projection_a_to_a,
-                &projection_b_to_a,
-                2,
-            ));
-            (15, 1, pooled)
-        }),
-        Box::new(|| {
-            let pooled = pooling.pool(&spatial_metrics::point_projected_distances(
-                &projection_a_to_a,
-            ));
-            (16, 2, pooled)
-        }),
-        Box::new(|| {
-            let pooled = pooling.pool(&spatial_metrics::point_to_centroid_distances(
-                &projection_b_to_a,
-            ));
-            (18, 1, pooled)
-        }),
-        Box::new(|| {
-            let pooled = pooling.pool(&spatial_metrics::point_projected_distances(
-                &projection_b_to_a,
-            ));
-            (19, 2, pooled)
-        }),
-        Box::new(|| {
-            let pooled = pooling.pool(&spatial_metrics::point_to_centroid_distances(
-                &points_mean_b,
-            ));
-            (21, 1, pooled)
-        }),
-        Box::new(|| {
-            let pooled = pooling.pool(&spatial_metrics::point_projected_distances(&points_mean_b));
-            (22, 2, pooled)
-        }),
-        Box::new(|| {
-            let pooled = pooling.pool(&spatial_metrics::iter_relative_difference(
-                &points_variance_a,
-                &points_variance_b,
-            ));
-            (24, 3, pooled)
-        }),
-        Box::new(|| {
-            let pooled = pooling.pool(&spatial_metrics::covariance_differences(
-                &points_variance_a,
-                &points_variance_b,
-                &points_covariance_ab,
-            ));
-            (27, 3, pooled)
-        }),
-        Box::new(|| {
-            let pooled = pooling.pool(&spatial_metrics::omnivariance_differences(
-                &points_variance_a,
-                &points_variance_b,
-            ));
-            (30, 1, pooled)
-        }),
-        Box::new(|| {
-            let pooled = pooling.pool(&spatial_metrics::entropy(
-                &points_variance_a,
-                &points_variance_b,
-            ));
-            (31, 1, pooled)
-        }),
-        Box::new(|| {
-            let pooled = pooling.pool(&spatial_metrics::anisotropy_planarity_linearity(
-                &points_variance_a,
-                &points_variance_b,
-                0,
-                2,
-            ));
-            (32, 1, pooled)
-        }),
-        Box::new(|| {
-            let pooled = pooling.pool(&spatial_metrics::anisotropy_planarity_linearity(
-                &points_variance_a,
-                &points_variance_b,
-                1,
-                2,
-            ));
-            (33, 1, pooled)
-        }),
-        Box::new(|| {
-            let pooled = pooling.pool(&spatial_metrics::anisotropy_planarity_linearity(
-                &points_variance_a,
-                &points_variance_b,
-                0,
-                1,
-            ));
-            (34, 1, pooled)
-        }),
-        Box::new(|| {
-            let pooled = pooling.pool(&spatial_metrics::surface_variation(
-                &points_variance_a,
-                &points_variance_b,
-            ));
-            (35, 1, pooled)
-        }),
-        Box::new(|| {
-            let pooled = pooling.pool(&spatial_metrics::sphericity(
-                &points_variance_a,
-                &points_variance_b,
-            ));
-            (36, 1, pooled)
-        }),
-        Box::new(|| {
-            let pooled = pooling.pool(&spatial_metrics::angular_similarity(&eigenvectors_b_y));
-            (37, 1, pooled)
-        }),
-        Box::new(|| {
-            let pooled = pooling.pool(&spatial_metrics::parallelity(&eigenvectors_b_x, 0));
-            (38, 1, pooled)
-        }),
-        Box::new(|| {
-            let pooled = pooling.pool(&spatial_metrics::parallelity(&eigenvectors_b_z, 2));
-            (39, 1, pooled)
-        }),
-    ];
-    // Compute all predictors in parallel
-    let results: Vec<(usize, usize, Matrix1xX<f64>)> = predictor_computations
-        .into_par_iter()
-        .map(|computation| computation())
-        .collect();
-    // Copy results back to the predictors matrix
-    for (start_col, num_cols, values) in results {
-        predictors
-            .columns_mut(start_col, num_cols)
-            .copy_from(&values);
-    }
-    predictors
+    PredictorRegistry::builtin()
+        .compute_predictors(local_features)
+        .expect("Built-in predictors return as many columns as their width.")
 }
@@ -1,5 +1,31 @@
+use crate::knn_search;
 use ordered_float::OrderedFloat;
-use std::collections::BTreeMap;
+use rayon::prelude::*;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutlierFilter {
+    // Removes points whose mean distance to their k nearest neighbors exceeds
+    // the cloud-wide mean of that distance by more than std_ratio standard deviations
+    Statistical { k: usize, std_ratio: f64 },
+    // Removes points with fewer than min_neighbors other points within radius
+    Radius { radius: f64, min_neighbors: usize },
+}
+
+#[derive(Clone, Debug)]
+pub struct PreprocessReport {
+    pub input_points: usize,
+    pub output_points: usize,
+    pub merged_duplicates: usize,
+    // Largest number of input points sharing a single location
+    pub max_duplicates: usize,
+    pub outliers_removed: usize,
+    // Minimum and maximum corners of the preprocessed cloud
+    pub bounding_box: [[f64; 3]; 2],
+    pub mean_nn_spacing: f64,
+    pub median_nn_spacing: f64,
+    // Histograms of the Y, U and V channels of the preprocessed colors
+    pub color_histograms: [[usize; 256]; 3],
+}
 
 #[derive(PartialEq, Eq, PartialOrd, Ord)]
 struct OrderedPoint(OrderedFloat<f64>, OrderedFloat<f64>, OrderedFloat<f64>);
@@ -12,7 +38,7 @@ fn to_ordered_point(num: [f64; 3]) -> OrderedPoint {
     )
 }
 
-fn from_ordered_point(num: OrderedPoint) -> [f64; 3] {
+fn from_ordered_point(num: &OrderedPoint) -> [f64; 3] {
     [num.0.into(), num.1.into(), num.2.into()]
 }
 
@@ -30,7 +56,7 @@ fn rgb_to_yuv(rgb: [u8; 3]) -> [u8; 3] {
     ]
 }
 
-fn mean_colors(colors: Vec<[u8; 3]>) -> [u8; 3] {
+fn mean_colors(colors: impl ExactSizeIterator<Item = [u8; 3]>) -> [u8; 3] {
     let vec_len = colors.len() as f64;
     let mut vec_sum: (f64, f64, f64) = (0.0, 0.0, 0.0);
     for color in colors {
@@ -43,35 +69,193 @@ fn mean_colors(colors: Vec<[u8; 3]>) -> [u8; 3] {
         vec_sum.1 / vec_len,
         vec_sum.2 / vec_len,
     );
-    let rounded_mean = [
+    [
         mean.0.round() as u8,
         mean.1.round() as u8,
         mean.2.round() as u8,
-    ];
-    rounded_mean
+    ]
+}
+
+fn merge_duplicates(
+    points: Vec<[f64; 3]>,
+    colors: Vec<[u8; 3]>,
+) -> (Vec<[f64; 3]>, Vec<[u8; 3]>, usize) {
+    assert_eq!(
+        points.len(),
+        colors.len(),
+        "Points and colors must have the same length."
+    );
+    let mut entries = points
+        .into_par_iter()
+        .map(to_ordered_point)
+        .zip(colors)
+        .collect::<Vec<(OrderedPoint, [u8; 3])>>();
+    // Stable sort, so each group of duplicates keeps the first occurrence of its point
+    entries.par_sort_by(|a, b| a.0.cmp(&b.0));
+    let nentries = entries.len();
+    let group_starts = (0..nentries)
+        .into_par_iter()
+        .filter(|&i| i == 0 || entries[i].0 != entries[i - 1].0)
+        .collect::<Vec<usize>>();
+    let group_end = |i: usize| group_starts.get(i + 1).copied().unwrap_or(nentries);
+    let max_duplicates = (0..group_starts.len())
+        .into_par_iter()
+        .map(|i| group_end(i) - group_starts[i])
+        .max()
+        .unwrap_or(0);
+    let (points_result, colors_result) = group_starts
+        .par_iter()
+        .enumerate()
+        .map(|(i, &start)| {
+            let group = &entries[start..group_end(i)];
+            let unique_point = from_ordered_point(&group[0].0);
+            let mean_color = mean_colors(group.iter().map(|(_, color)| *color));
+            let color_yuv = rgb_to_yuv(mean_color);
+            (unique_point, color_yuv)
+        })
+        .unzip();
+    (points_result, colors_result, max_duplicates)
 }
 
 pub fn preprocess_point_cloud(
     points: Vec<[f64; 3]>,
     colors: Vec<[u8; 3]>,
 ) -> (Vec<[f64; 3]>, Vec<[u8; 3]>) {
-    let mut points_map: BTreeMap<OrderedPoint, Vec<[u8; 3]>> = BTreeMap::new();
-    for i in 0..points.len() {
-        let point = to_ordered_point(points[i]);
-        points_map
-            .entry(point)
-            .or_insert_with(Vec::new)
-            .push(colors[i]);
+    let (points_result, colors_result, _) = merge_duplicates(points, colors);
+    (points_result, colors_result)
+}
+
+fn bounding_box(points: &[[f64; 3]]) -> [[f64; 3]; 2] {
+    if points.is_empty() {
+        return [[0.; 3]; 2];
     }
-    let nrows = points_map.len();
-    let mut points_result = Vec::with_capacity(nrows);
-    let mut colors_result = Vec::with_capacity(nrows);
-    for (ordered_point, colors) in points_map {
-        let unique_point = from_ordered_point(ordered_point);
-        let mean_color = mean_colors(colors);
-        let color_yuv = rgb_to_yuv(mean_color);
-        points_result.push(unique_point);
-        colors_result.push(color_yuv);
+    let mut bounds = [[f64::INFINITY; 3], [f64::NEG_INFINITY; 3]];
+    for point in points {
+        for j in 0..3 {
+            bounds[0][j] = bounds[0][j].min(point[j]);
+            bounds[1][j] = bounds[1][j].max(point[j]);
+        }
     }
-    (points_result, colors_result)
+    bounds
+}
+
+fn nn_spacing(points: &[[f64; 3]]) -> (f64, f64) {
+    if points.len() < 2 {
+        return (0., 0.);
+    }
+    let kdtree = knn_search::build_tree(points);
+    // The first neighbor is the query point itself
+    let mut spacings = points
+        .par_iter()
+        .map(|point| knn_search::nearest_n_with_distances(&kdtree, point, 2)[1].1)
+        .collect::<Vec<f64>>();
+    spacings.par_sort_by(|a, b| a.total_cmp(b));
+    let len = spacings.len();
+    let mean = spacings.iter().sum::<f64>() / len as f64;
+    let median = if len % 2 == 0 {
+        (spacings[len / 2] + spacings[len / 2 - 1]) / 2.
+    } else {
+        spacings[len / 2]
+    };
+    (mean, median)
+}
+
+fn color_histograms(colors: &[[u8; 3]]) -> [[usize; 256]; 3] {
+    let mut histograms = [[0; 256]; 3];
+    for color in colors {
+        for j in 0..3 {
+            histograms[j][color[j] as usize] += 1;
+        }
+    }
+    histograms
+}
+
+pub fn preprocess_point_cloud_with_report(
+    points: Vec<[f64; 3]>,
+    colors: Vec<[u8; 3]>,
+    outlier_filter: Option<&OutlierFilter>,
+) -> (Vec<[f64; 3]>, Vec<[u8; 3]>, PreprocessReport) {
+    let input_points = points.len();
+    let (points, colors, max_duplicates) = merge_duplicates(points, colors);
+    let merged_duplicates = input_points - points.len();
+    let (points, colors, outliers_removed) = match outlier_filter {
+        Some(filter) => remove_outliers(points, colors, filter),
+        None => (points, colors, 0),
+    };
+    let (mean_nn_spacing, median_nn_spacing) = nn_spacing(&points);
+    let report = PreprocessReport {
+        input_points,
+        output_points: points.len(),
+        merged_duplicates,
+        max_duplicates,
+        outliers_removed,
+        bounding_box: bounding_box(&points),
+        mean_nn_spacing,
+        median_nn_spacing,
+        color_histograms: color_histograms(&colors),
+    };
+    (points, colors, report)
+}
+
+fn statistical_inliers(points: &[[f64; 3]], k: usize, std_ratio: f64) -> Vec<bool> {
+    let kdtree = knn_search::build_tree(points);
+    // The query point is its own nearest neighbor, so k + 1 neighbors are searched
+    let mean_distances = points
+        .par_iter()
+        .map(|point| {
+            let neighbors = knn_search::nearest_n_with_distances(&kdtree, point, k + 1);
+            let distances = neighbors.iter().skip(1).map(|&(_, dist)| dist);
+            let count = neighbors.len().saturating_sub(1).max(1) as f64;
+            distances.sum::<f64>() / count
+        })
+        .collect::<Vec<f64>>();
+    let npoints = mean_distances.len() as f64;
+    let mean = mean_distances.iter().sum::<f64>() / npoints;
+    let variance = mean_distances
+        .iter()
+        .map(|dist| (dist - mean).powi(2))
+        .sum::<f64>()
+        / npoints;
+    let threshold = mean + std_ratio * variance.sqrt();
+    mean_distances
+        .iter()
+        .map(|&dist| dist <= threshold)
+        .collect()
+}
+
+fn radius_inliers(points: &[[f64; 3]], radius: f64, min_neighbors: usize) -> Vec<bool> {
+    let kdtree = knn_search::build_tree(points);
+    // The query point always lies within its own radius and is not counted
+    points
+        .par_iter()
+        .map(|point| {
+            let neighbors = knn_search::within_radius(&kdtree, point, radius);
+            neighbors.len().saturating_sub(1) >= min_neighbors
+        })
+        .collect()
+}
+
+pub fn remove_outliers(
+    points: Vec<[f64; 3]>,
+    colors: Vec<[u8; 3]>,
+    filter: &OutlierFilter,
+) -> (Vec<[f64; 3]>, Vec<[u8; 3]>, usize) {
+    if points.is_empty() {
+        return (points, colors, 0);
+    }
+    let inliers = match *filter {
+        OutlierFilter::Statistical { k, std_ratio } => statistical_inliers(&points, k, std_ratio),
+        OutlierFilter::Radius {
+            radius,
+            min_neighbors,
+        } => radius_inliers(&points, radius, min_neighbors),
+    };
+    let nremoved = inliers.iter().filter(|&&inlier| !inlier).count();
+    let (points_result, colors_result) = points
+        .into_iter()
+        .zip(colors)
+        .zip(inliers)
+        .filter_map(|(point_color, inlier)| inlier.then_some(point_color))
+        .unzip();
+    (points_result, colors_result, nremoved)
 }
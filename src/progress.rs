@@ -0,0 +1,163 @@
+use std::fmt;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    Preprocess,
+    IndexBuild,
+    Features,
+    Predictors,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Stage::Preprocess => "Preprocessing",
+            Stage::IndexBuild => "Building neighbor indexes",
+            Stage::Features => "Computing local features",
+            Stage::Predictors => "Computing predictors",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Receives the progress of a run. Callbacks may come from worker threads, and point counts
+// from concurrent threads may arrive slightly out of order. In chunked runs the predictors
+// of each chunk are computed between chunks of features, so both stages overlap and their
+// reported durations are summed over the chunks
+pub trait ProgressSink: fmt::Debug + Send + Sync {
+    fn stage_started(&self, _stage: Stage) {}
+
+    fn stage_finished(&self, _stage: Stage, _elapsed: Duration) {}
+
+    // Query points whose local features are computed, over all of them (times the number
+    // of scales in multi-scale runs)
+    fn points_processed(&self, _processed: usize, _total: usize) {}
+
+    // Details about the run, such as removed outliers or the applied policies
+    fn message(&self, _message: &str) {}
+}
+
+// Reports a stage whose work may be split in several steps, starting it with the first step
+// and summing the durations of the steps
+pub struct StageTimer {
+    progress: Arc<dyn ProgressSink>,
+    stage: Stage,
+    elapsed: Option<Duration>,
+}
+
+impl StageTimer {
+    pub fn new(progress: &Arc<dyn ProgressSink>, stage: Stage) -> Self {
+        Self {
+            progress: progress.clone(),
+            stage,
+            elapsed: None,
+        }
+    }
+
+    pub fn time<R>(&mut self, op: impl FnOnce() -> R) -> R {
+        let elapsed = *self.elapsed.get_or_insert_with(|| {
+            self.progress.stage_started(self.stage);
+            Duration::ZERO
+        });
+        let start = Instant::now();
+        let result = op();
+        self.elapsed = Some(elapsed + start.elapsed());
+        result
+    }
+
+    // Reports the end of the stage, if it was started
+    pub fn finish(self) {
+        if let Some(elapsed) = self.elapsed {
+            self.progress.stage_finished(self.stage, elapsed);
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoProgress;
+
+impl ProgressSink for NoProgress {}
+
+// Stage names, timings and a progress bar of the local features on stderr
+#[derive(Debug, Default)]
+pub struct StderrProgress {
+    // Last drawn percentage of the progress bar
+    percent: Mutex<Option<usize>>,
+}
+
+const BAR_WIDTH: usize = 40;
+
+impl StderrProgress {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn end_bar(&self) {
+        if self.percent.lock().unwrap().take().is_some() {
+            eprintln!();
+        }
+    }
+}
+
+impl ProgressSink for StderrProgress {
+    fn stage_started(&self, stage: Stage) {
+        self.end_bar();
+        eprintln!("{}", stage);
+    }
+
+    fn stage_finished(&self, stage: Stage, elapsed: Duration) {
+        self.end_bar();
+        eprintln!("{} done in {:.3}s", stage, elapsed.as_secs_f64());
+    }
+
+    fn points_processed(&self, processed: usize, total: usize) {
+        let percent = (100 * processed).checked_div(total).unwrap_or(100);
+        let mut last = self.percent.lock().unwrap();
+        if last.is_some_and(|last| last >= percent) {
+            return;
+        }
+        *last = Some(percent);
+        let filled = BAR_WIDTH * percent / 100;
+        eprint!(
+            "\r[{}{}] {:>3}% ({}/{})",
+            "#".repeat(filled),
+            " ".repeat(BAR_WIDTH - filled),
+            percent,
+            processed,
+            total
+        );
+        let _ = std::io::stderr().flush();
+    }
+
+    fn message(&self, message: &str) {
+        self.end_bar();
+        eprintln!("{}", message);
+    }
+}
+
+// Forwards the progress to the log crate, which tracing subscribers can also collect
+#[cfg(feature = "log")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LogProgress;
+
+#[cfg(feature = "log")]
+impl ProgressSink for LogProgress {
+    fn stage_started(&self, stage: Stage) {
+        log::info!("{}", stage);
+    }
+
+    fn stage_finished(&self, stage: Stage, elapsed: Duration) {
+        log::info!("{} done in {:.3}s", stage, elapsed.as_secs_f64());
+    }
+
+    fn points_processed(&self, processed: usize, total: usize) {
+        log::debug!("Processed {}/{} points", processed, total);
+    }
+
+    fn message(&self, message: &str) {
+        log::info!("{}", message);
+    }
+}
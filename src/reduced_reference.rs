@@ -0,0 +1,434 @@
+use crate::error::PointPca2Error;
+use crate::knn_search::Neighborhood;
+use crate::local_geometry::{self, LocalGeometryConfig, NormalOrientation};
+use crate::pooling;
+use crate::predictors::Columns;
+use crate::preprocessing;
+use crate::spatial_metrics;
+use na::{DMatrix, Matrix1xX};
+use std::collections::BTreeMap;
+
+const SIGNATURE_MAGIC: &[u8; 4] = b"PPRR";
+const SIGNATURE_VERSION: u8 = 1;
+// Cell coordinates, point count and nine f64 statistics
+const REGION_BYTES: usize = 4 * 4 + 9 * 8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReducedReferenceConfig {
+    // Nearest neighbors of the local PCA, on both sides
+    pub search_size: usize,
+    // Cells of the coarse grid along the longest side of the reference bounding box
+    pub grid_resolution: usize,
+}
+
+impl ReducedReferenceConfig {
+    pub fn new(search_size: usize) -> Self {
+        Self {
+            search_size,
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for ReducedReferenceConfig {
+    fn default() -> Self {
+        Self {
+            search_size: 81,
+            grid_resolution: 8,
+        }
+    }
+}
+
+// Statistics of the points of one grid cell
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RegionStatistics {
+    pub cell: [u32; 3],
+    pub points: u32,
+    // Means of the local PCA eigenvalues, in descending order
+    pub eigenvalues_mean: [f64; 3],
+    // Means and variances of the YUV colors
+    pub colors_mean: [f64; 3],
+    pub colors_variance: [f64; 3],
+}
+
+// Compact description of a reference cloud, from which the receiver computes reduced
+// reference predictors without the cloud itself
+#[derive(Clone, Debug, PartialEq)]
+pub struct Signature {
+    pub search_size: usize,
+    // Minimum corner of the grid, edge length of its cubic cells and cells per axis
+    pub origin: [f64; 3],
+    pub cell_size: f64,
+    pub dimensions: [u32; 3],
+    // Occupied cells of the reference cloud, in increasing cell order
+    pub regions: Vec<RegionStatistics>,
+}
+
+pub struct ReducedReferenceResult {
+    pub predictors: Matrix1xX<f64>,
+    pub predictor_names: Vec<String>,
+    // Reference regions without any distorted point, whose statistics are compared to zeros
+    pub missing_regions: usize,
+    // Cells occupied by the distorted cloud only, which are not compared
+    pub extra_regions: usize,
+}
+
+impl Signature {
+    // Cell of a point, points outside the grid falling in the nearest border cell
+    fn cell(&self, point: &[f64; 3]) -> [u32; 3] {
+        let mut cell = [0; 3];
+        for j in 0..3 {
+            let position = ((point[j] - self.origin[j]) / self.cell_size).floor();
+            cell[j] = position.clamp(0., (self.dimensions[j] - 1) as f64) as u32;
+        }
+        cell
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(57 + self.regions.len() * REGION_BYTES);
+        bytes.extend_from_slice(SIGNATURE_MAGIC);
+        bytes.push(SIGNATURE_VERSION);
+        bytes.extend_from_slice(&(self.search_size as u32).to_le_bytes());
+        for x in self.origin.iter().chain([&self.cell_size]) {
+            bytes.extend_from_slice(&x.to_le_bytes());
+        }
+        for n in self.dimensions.iter().chain([&(self.regions.len() as u32)]) {
+            bytes.extend_from_slice(&n.to_le_bytes());
+        }
+        for region in &self.regions {
+            for n in region.cell.iter().chain([&region.points]) {
+                bytes.extend_from_slice(&n.to_le_bytes());
+            }
+            let statistics = region
+                .eigenvalues_mean
+                .iter()
+                .chain(&region.colors_mean)
+                .chain(&region.colors_variance);
+            for x in statistics {
+                bytes.extend_from_slice(&x.to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, PointPca2Error> {
+        let mut reader = Reader { bytes };
+        if reader.take(4)? != SIGNATURE_MAGIC {
+            return Err(invalid("not a reduced reference signature"));
+        }
+        if reader.take(1)?[0] != SIGNATURE_VERSION {
+            return Err(invalid("unsupported signature version"));
+        }
+        let search_size = reader.u32()? as usize;
+        let origin = [reader.f64()?, reader.f64()?, reader.f64()?];
+        let cell_size = reader.f64()?;
+        let dimensions = [reader.u32()?, reader.u32()?, reader.u32()?];
+        let nregions = reader.u32()? as usize;
+        if dimensions.contains(&0) || !cell_size.is_finite() || cell_size <= 0. {
+            return Err(invalid("empty grid"));
+        }
+        if reader.bytes.len() != nregions * REGION_BYTES {
+            return Err(invalid("size does not match the number of regions"));
+        }
+        let mut regions = Vec::with_capacity(nregions);
+        for _ in 0..nregions {
+            let mut region = RegionStatistics {
+                cell: [reader.u32()?, reader.u32()?, reader.u32()?],
+                points: reader.u32()?,
+                ..Default::default()
+            };
+            for x in region
+                .eigenvalues_mean
+                .iter_mut()
+                .chain(&mut region.colors_mean)
+                .chain(&mut region.colors_variance)
+            {
+                *x = reader.f64()?;
+            }
+            regions.push(region);
+        }
+        Ok(Self {
+            search_size,
+            origin,
+            cell_size,
+            dimensions,
+            regions,
+        })
+    }
+}
+
+fn invalid(message: &str) -> PointPca2Error {
+    PointPca2Error::InvalidSignature {
+        message: message.to_string(),
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], PointPca2Error> {
+        if self.bytes.len() < n {
+            return Err(invalid("truncated signature"));
+        }
+        let (head, tail) = self.bytes.split_at(n);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    fn u32(&mut self) -> Result<u32, PointPca2Error> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn f64(&mut self) -> Result<f64, PointPca2Error> {
+        Ok(f64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+// Grid of cubic cells covering the bounding box, with resolution cells along its longest side
+fn grid(points: &[[f64; 3]], resolution: usize) -> ([f64; 3], f64, [u32; 3]) {
+    let mut min = [f64::INFINITY; 3];
+    let mut max = [f64::NEG_INFINITY; 3];
+    for point in points {
+        for j in 0..3 {
+            min[j] = min[j].min(point[j]);
+            max[j] = max[j].max(point[j]);
+        }
+    }
+    let extent = (0..3).map(|j| max[j] - min[j]).fold(0., f64::max);
+    // A single location still gets one cell
+    let cell_size = if extent > 0. {
+        extent / resolution.max(1) as f64
+    } else {
+        1.
+    };
+    let dimensions = [0, 1, 2].map(|j| {
+        (((max[j] - min[j]) / cell_size).floor() as u32 + 1).min(resolution.max(1) as u32)
+    });
+    (min, cell_size, dimensions)
+}
+
+// Statistics of every occupied cell of a preprocessed cloud
+fn region_statistics(
+    points: &[[f64; 3]],
+    colors: &[[u8; 3]],
+    signature: &Signature,
+) -> BTreeMap<[u32; 3], RegionStatistics> {
+    let config = LocalGeometryConfig {
+        neighborhood: Neighborhood::Knn(signature.search_size),
+        normal_orientation: NormalOrientation::None,
+        ..Default::default()
+    };
+    let geometry = local_geometry::compute_local_geometry(points, &config);
+    let cells = points
+        .iter()
+        .map(|point| signature.cell(point))
+        .collect::<Vec<_>>();
+    let mut regions = BTreeMap::<[u32; 3], RegionStatistics>::new();
+    for ((cell, point_geometry), color) in cells.iter().zip(&geometry).zip(colors) {
+        let region = regions.entry(*cell).or_insert_with(|| RegionStatistics {
+            cell: *cell,
+            ..Default::default()
+        });
+        region.points += 1;
+        for (j, &c) in color.iter().enumerate() {
+            region.eigenvalues_mean[j] += point_geometry.eigenvalues[j];
+            region.colors_mean[j] += c as f64;
+        }
+    }
+    for region in regions.values_mut() {
+        let npoints = region.points as f64;
+        for j in 0..3 {
+            region.eigenvalues_mean[j] /= npoints;
+            region.colors_mean[j] /= npoints;
+        }
+    }
+    // Variances around the means, in a second pass
+    for (cell, color) in cells.iter().zip(colors) {
+        let region = regions.get_mut(cell).expect("Every cell was counted");
+        for (j, &c) in color.iter().enumerate() {
+            region.colors_variance[j] += (c as f64 - region.colors_mean[j]).powi(2);
+        }
+    }
+    for region in regions.values_mut() {
+        let npoints = region.points as f64;
+        for j in 0..3 {
+            region.colors_variance[j] /= npoints;
+        }
+    }
+    regions
+}
+
+// Signature of a reference cloud, preprocessed like the reference of compute_pointpca2
+pub fn compute_signature(
+    points: Vec<[f64; 3]>,
+    colors: Vec<[u8; 3]>,
+    config: &ReducedReferenceConfig,
+) -> Result<Signature, PointPca2Error> {
+    let (points, colors) = preprocessing::preprocess_point_cloud(points, colors);
+    if points.is_empty() {
+        return Err(PointPca2Error::EmptyCloud { cloud: "reference" });
+    }
+    let (origin, cell_size, dimensions) = grid(&points, config.grid_resolution);
+    let mut signature = Signature {
+        search_size: config.search_size,
+        origin,
+        cell_size,
+        dimensions,
+        regions: Vec::new(),
+    };
+    signature.regions = region_statistics(&points, &colors, &signature)
+        .into_values()
+        .collect();
+    Ok(signature)
+}
+
+const CHANNELS: [&str; 3] = ["y", "u", "v"];
+const AXES: [&str; 3] = ["e1", "e2", "e3"];
+
+// Rows of one statistic over the regions, reference regions missing from the distorted cloud
+// contributing zeros
+fn statistic_rows(
+    regions: &[&RegionStatistics],
+    statistic: impl Fn(&RegionStatistics) -> [f64; 3],
+) -> DMatrix<f64> {
+    DMatrix::from_fn(regions.len(), 3, |i, j| statistic(regions[i])[j])
+}
+
+fn view(matrix: &DMatrix<f64>) -> Columns<'_> {
+    matrix.columns(0, matrix.ncols())
+}
+
+// Reduced reference predictors of a distorted cloud against a signature. Each predictor
+// compares the statistics of every reference region with those of the distorted points in
+// the same cell, using the formulas of the PointPCA2 predictors, and is mean pooled over the
+// reference regions
+pub fn compute_reduced_reference(
+    signature: &Signature,
+    points: Vec<[f64; 3]>,
+    colors: Vec<[u8; 3]>,
+) -> Result<ReducedReferenceResult, PointPca2Error> {
+    let (points, colors) = preprocessing::preprocess_point_cloud(points, colors);
+    if points.is_empty() {
+        return Err(PointPca2Error::EmptyCloud { cloud: "distorted" });
+    }
+    let distorted = region_statistics(&points, &colors, signature);
+    let missing = RegionStatistics::default();
+    let regions_b = signature
+        .regions
+        .iter()
+        .map(|region| distorted.get(&region.cell).unwrap_or(&missing))
+        .collect::<Vec<_>>();
+    let regions_a = signature.regions.iter().collect::<Vec<_>>();
+    let missing_regions = regions_b.iter().filter(|region| region.points == 0).count();
+    let extra_regions = distorted
+        .keys()
+        .filter(|cell| {
+            signature
+                .regions
+                .binary_search_by(|region| region.cell.cmp(cell))
+                .is_err()
+        })
+        .count();
+    let [eigenvalues_a, eigenvalues_b] =
+        [&regions_a, &regions_b].map(|regions| statistic_rows(regions, |r| r.eigenvalues_mean));
+    let [colors_mean_a, colors_mean_b] =
+        [&regions_a, &regions_b].map(|regions| statistic_rows(regions, |r| r.colors_mean));
+    let [colors_variance_a, colors_variance_b] =
+        [&regions_a, &regions_b].map(|regions| statistic_rows(regions, |r| r.colors_variance));
+    let [points_a, points_b] = [&regions_a, &regions_b]
+        .map(|regions| DMatrix::from_fn(regions.len(), 1, |i, _| regions[i].points as f64));
+    let (eigenvalues_a, eigenvalues_b) = (view(&eigenvalues_a), view(&eigenvalues_b));
+    let (colors_variance_a, colors_variance_b) =
+        (view(&colors_variance_a), view(&colors_variance_b));
+    let columns = [
+        (
+            "color_mean",
+            &CHANNELS[..],
+            spatial_metrics::iter_relative_difference(&view(&colors_mean_a), &view(&colors_mean_b)),
+        ),
+        (
+            "color_variance",
+            &CHANNELS[..],
+            spatial_metrics::iter_relative_difference(&colors_variance_a, &colors_variance_b),
+        ),
+        (
+            "color_variance_sum",
+            &[][..],
+            spatial_metrics::textural_variance_sum(&colors_variance_a, &colors_variance_b),
+        ),
+        (
+            "color_omnivariance",
+            &[][..],
+            spatial_metrics::omnivariance_differences(&colors_variance_a, &colors_variance_b),
+        ),
+        (
+            "color_entropy",
+            &[][..],
+            spatial_metrics::entropy(&colors_variance_a, &colors_variance_b),
+        ),
+        (
+            "eigenvalue",
+            &AXES[..],
+            spatial_metrics::iter_relative_difference(&eigenvalues_a, &eigenvalues_b),
+        ),
+        (
+            "omnivariance",
+            &[][..],
+            spatial_metrics::omnivariance_differences(&eigenvalues_a, &eigenvalues_b),
+        ),
+        (
+            "entropy",
+            &[][..],
+            spatial_metrics::entropy(&eigenvalues_a, &eigenvalues_b),
+        ),
+        (
+            "anisotropy",
+            &[][..],
+            spatial_metrics::anisotropy_planarity_linearity(&eigenvalues_a, &eigenvalues_b, 0, 2),
+        ),
+        (
+            "planarity",
+            &[][..],
+            spatial_metrics::anisotropy_planarity_linearity(&eigenvalues_a, &eigenvalues_b, 1, 2),
+        ),
+        (
+            "linearity",
+            &[][..],
+            spatial_metrics::anisotropy_planarity_linearity(&eigenvalues_a, &eigenvalues_b, 0, 1),
+        ),
+        (
+            "surface_variation",
+            &[][..],
+            spatial_metrics::surface_variation(&eigenvalues_a, &eigenvalues_b),
+        ),
+        (
+            "sphericity",
+            &[][..],
+            spatial_metrics::sphericity(&eigenvalues_a, &eigenvalues_b),
+        ),
+        (
+            "density",
+            &[][..],
+            spatial_metrics::iter_relative_difference(&view(&points_a), &view(&points_b)),
+        ),
+    ];
+    let pooling = pooling::Pool::new("mean_pooling").unwrap();
+    let mut predictors = Vec::new();
+    let mut predictor_names = Vec::new();
+    for (name, suffixes, values) in &columns {
+        predictors.extend(pooling.pool(values).iter().copied());
+        if suffixes.is_empty() {
+            predictor_names.push(name.to_string());
+        } else {
+            predictor_names.extend(suffixes.iter().map(|suffix| format!("{}_{}", name, suffix)));
+        }
+    }
+    Ok(ReducedReferenceResult {
+        predictors: Matrix1xX::from_vec(predictors),
+        predictor_names,
+        missing_regions,
+        extra_regions,
+    })
+}
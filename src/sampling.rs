@@ -0,0 +1,173 @@
+use crate::utils::{self, SplitMix64};
+use na::{DMatrix, Matrix1xX};
+use rayon::prelude::*;
+use std::collections::HashMap;
+
+// Reference points at which the local features are computed. Neighbors are still searched
+// in the full clouds, only the pooled points change
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum QuerySampling {
+    #[default]
+    All,
+    // count points drawn uniformly without replacement
+    Uniform {
+        count: usize,
+        seed: u64,
+    },
+    // count points, each the farthest from the ones already drawn, starting from the first
+    FarthestPoint {
+        count: usize,
+    },
+    // The point closest to the center of each occupied voxel of the given size
+    Voxel {
+        size: f64,
+    },
+}
+
+// Percentile bootstrap of the mean pooled predictors
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bootstrap {
+    pub resamples: usize,
+    // Coverage of the intervals, e.g. 0.95
+    pub confidence: f64,
+    pub seed: u64,
+}
+
+impl Bootstrap {
+    pub fn new(resamples: usize, confidence: f64) -> Self {
+        Self {
+            resamples,
+            confidence,
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for Bootstrap {
+    fn default() -> Self {
+        Self {
+            resamples: 1000,
+            confidence: 0.95,
+            seed: 0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConfidenceIntervals {
+    pub lower: Matrix1xX<f64>,
+    pub upper: Matrix1xX<f64>,
+}
+
+fn squared_distance(a: &[f64; 3], b: &[f64; 3]) -> f64 {
+    (0..3).map(|j| (a[j] - b[j]).powi(2)).sum()
+}
+
+fn farthest_point_indices(points: &[[f64; 3]], count: usize) -> Vec<usize> {
+    let mut distances = vec![f64::INFINITY; points.len()];
+    let mut sample = Vec::with_capacity(count.min(points.len()));
+    let mut next = 0;
+    while sample.len() < count.min(points.len()) {
+        sample.push(next);
+        let chosen = points[next];
+        distances
+            .par_iter_mut()
+            .zip(points)
+            .for_each(|(distance, point)| {
+                *distance = distance.min(squared_distance(point, &chosen))
+            });
+        // Farthest point, the lowest index among ties
+        let (farthest, index) = distances
+            .par_iter()
+            .enumerate()
+            .map(|(i, &distance)| (distance, i))
+            .reduce(
+                || (f64::NEG_INFINITY, usize::MAX),
+                |a, b| {
+                    if b.0 > a.0 || (b.0 == a.0 && b.1 < a.1) {
+                        b
+                    } else {
+                        a
+                    }
+                },
+            );
+        // The remaining points coincide with drawn ones
+        if farthest <= 0. {
+            break;
+        }
+        next = index;
+    }
+    sample.sort_unstable();
+    sample
+}
+
+fn voxel_indices(points: &[[f64; 3]], size: f64) -> Vec<usize> {
+    assert!(size > 0., "The voxel size must be positive.");
+    let mut voxels: HashMap<[i64; 3], (f64, usize)> = HashMap::new();
+    for (i, point) in points.iter().enumerate() {
+        let voxel = point.map(|x| (x / size).floor() as i64);
+        let center = voxel.map(|v| (v as f64 + 0.5) * size);
+        let distance = squared_distance(point, &center);
+        let closest = voxels.entry(voxel).or_insert((distance, i));
+        if distance < closest.0 {
+            *closest = (distance, i);
+        }
+    }
+    let mut sample = voxels.into_values().map(|(_, i)| i).collect::<Vec<_>>();
+    sample.sort_unstable();
+    sample
+}
+
+// Indices of the sampled points, in increasing order
+pub fn sample_queries(points: &[[f64; 3]], sampling: &QuerySampling) -> Vec<usize> {
+    match *sampling {
+        QuerySampling::All => (0..points.len()).collect(),
+        QuerySampling::Uniform { count, seed } => utils::sample_indices(points.len(), count, seed),
+        QuerySampling::FarthestPoint { count } => farthest_point_indices(points, count),
+        QuerySampling::Voxel { size } => voxel_indices(points, size),
+    }
+}
+
+// Linear interpolation between the closest ranks of sorted values
+fn percentile(sorted: &[f64], q: f64) -> f64 {
+    let rank = q * (sorted.len() - 1) as f64;
+    let (below, above) = (rank.floor() as usize, rank.ceil() as usize);
+    sorted[below] + (sorted[above] - sorted[below]) * (rank - below as f64)
+}
+
+// Percentile bootstrap intervals of the column means of rows, resampling its rows with
+// replacement. Each resample has its own generator, so the intervals do not depend on the
+// number of threads
+pub fn bootstrap_means(rows: &DMatrix<f64>, bootstrap: &Bootstrap) -> ConfidenceIntervals {
+    let (nrows, ncols) = rows.shape();
+    let mut seeds = SplitMix64::new(bootstrap.seed);
+    let seeds = (0..bootstrap.resamples.max(1))
+        .map(|_| seeds.next_u64())
+        .collect::<Vec<_>>();
+    let means = seeds
+        .par_iter()
+        .map(|&seed| {
+            let mut rng = SplitMix64::new(seed);
+            let mut sum = vec![0.; ncols];
+            for _ in 0..nrows {
+                let i = rng.next_below(nrows);
+                for (j, sum) in sum.iter_mut().enumerate() {
+                    *sum += rows[(i, j)];
+                }
+            }
+            sum.into_iter()
+                .map(|x| x / nrows as f64)
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let alpha = (1. - bootstrap.confidence.clamp(0., 1.)) / 2.;
+    let mut lower = Matrix1xX::zeros(ncols);
+    let mut upper = Matrix1xX::zeros(ncols);
+    for j in 0..ncols {
+        let mut column = means.iter().map(|mean| mean[j]).collect::<Vec<_>>();
+        column.sort_by(f64::total_cmp);
+        lower[j] = percentile(&column, alpha);
+        upper[j] = percentile(&column, 1. - alpha);
+    }
+    ConfidenceIntervals { lower, upper }
+}
@@ -1,16 +1,19 @@
-use libm::acos;
+use crate::precision::Real;
 use na::{DMatrix, MatrixView};
-use num_traits::Pow;
-use std::{f64::consts::PI, f64::EPSILON};
 
-pub fn relative_difference(x: f64, y: f64) -> f64 {
-    1. - (x - y).abs() / (x.abs() + y.abs() + EPSILON)
+// Regularization of the divisions, the f64 machine epsilon in every precision
+fn epsilon<T: Real>() -> T {
+    T::cast(f64::EPSILON)
 }
 
-pub fn iter_relative_difference<'a, T: na::Dim>(
-    x: &'a MatrixView<f64, T, T>,
-    y: &'a MatrixView<f64, T, T>,
-) -> DMatrix<f64> {
+pub fn relative_difference<T: Real>(x: T, y: T) -> T {
+    T::one() - (x - y).abs() / (x.abs() + y.abs() + epsilon())
+}
+
+pub fn iter_relative_difference<T: Real, D: na::Dim>(
+    x: &MatrixView<T, D, D>,
+    y: &MatrixView<T, D, D>,
+) -> DMatrix<T> {
     let nrows = x.nrows();
     let ncols = x.ncols();
     let mut result = DMatrix::zeros(nrows, ncols);
@@ -22,93 +25,93 @@ pub fn iter_relative_difference<'a, T: na::Dim>(
     result
 }
 
-pub fn covariance_differences<'a, T: na::Dim>(
-    x: &'a MatrixView<f64, T, T>,
-    y: &'a MatrixView<f64, T, T>,
-    z: &'a MatrixView<f64, T, T>,
-) -> DMatrix<f64> {
+pub fn covariance_differences<T: Real, D: na::Dim>(
+    x: &MatrixView<T, D, D>,
+    y: &MatrixView<T, D, D>,
+    z: &MatrixView<T, D, D>,
+) -> DMatrix<T> {
     let nrows = x.nrows();
     let ncols = x.ncols();
     let mut result = DMatrix::zeros(nrows, ncols);
     for i in 0..nrows {
         for j in 0..ncols {
             let sqrt_product_diff = (x[(i, j)].sqrt() * y[(i, j)].sqrt() - z[(i, j)]).abs();
-            let sqrt_product_norm = x[(i, j)].sqrt() * y[(i, j)].sqrt() + EPSILON;
+            let sqrt_product_norm = x[(i, j)].sqrt() * y[(i, j)].sqrt() + epsilon();
             result[(i, j)] = sqrt_product_diff / sqrt_product_norm;
         }
     }
     result
 }
 
-pub fn textural_variance_sum<'a, T: na::Dim>(
-    x: &'a MatrixView<f64, T, T>,
-    y: &'a MatrixView<f64, T, T>,
-) -> DMatrix<f64> {
+pub fn textural_variance_sum<T: Real, D: na::Dim>(
+    x: &MatrixView<T, D, D>,
+    y: &MatrixView<T, D, D>,
+) -> DMatrix<T> {
     let nrows = x.nrows();
     let mut result = DMatrix::zeros(nrows, 1);
     for i in 0..nrows {
-        let x_sum: f64 = x.row(i).sum();
-        let y_sum: f64 = y.row(i).sum();
+        let x_sum: T = x.row(i).sum();
+        let y_sum: T = y.row(i).sum();
         result[(i, 0)] = relative_difference(x_sum, y_sum);
     }
     result
 }
 
-pub fn omnivariance_differences<'a, T: na::Dim>(
-    x: &'a MatrixView<f64, T, T>,
-    y: &'a MatrixView<f64, T, T>,
-) -> DMatrix<f64> {
+pub fn omnivariance_differences<T: Real, D: na::Dim>(
+    x: &MatrixView<T, D, D>,
+    y: &MatrixView<T, D, D>,
+) -> DMatrix<T> {
     let nrows = x.nrows();
     let mut result = DMatrix::zeros(nrows, 1);
     for i in 0..nrows {
-        let x_prod: f64 = x.row(i).product();
-        let y_prod: f64 = y.row(i).product();
+        let x_prod: T = x.row(i).product();
+        let y_prod: T = y.row(i).product();
         result[(i, 0)] = relative_difference(x_prod.cbrt(), y_prod.cbrt());
     }
     result
 }
 
-pub fn entropy<'a, T: na::Dim>(
-    x: &'a MatrixView<f64, T, T>,
-    y: &'a MatrixView<f64, T, T>,
-) -> DMatrix<f64> {
+pub fn entropy<T: Real, D: na::Dim>(
+    x: &MatrixView<T, D, D>,
+    y: &MatrixView<T, D, D>,
+) -> DMatrix<T> {
     let nrows = x.nrows();
     let ncols = x.ncols();
     let mut result = DMatrix::zeros(nrows, 1);
     for i in 0..nrows {
-        let mut x_entropy: f64 = 0.;
-        let mut y_entropy: f64 = 0.;
+        let mut x_entropy: T = T::zero();
+        let mut y_entropy: T = T::zero();
         for j in 0..ncols {
-            x_entropy += x[(i, j)] * (x[(i, j)] + EPSILON).ln();
-            y_entropy += y[(i, j)] * (y[(i, j)] + EPSILON).ln();
+            x_entropy += x[(i, j)] * (x[(i, j)] + epsilon()).ln();
+            y_entropy += y[(i, j)] * (y[(i, j)] + epsilon()).ln();
         }
         result[(i, 0)] = relative_difference(x_entropy, y_entropy);
     }
     result
 }
 
-pub fn euclidean_distances<'a, T: na::Dim>(
-    x: &'a MatrixView<f64, T, T>,
-    y: &'a MatrixView<f64, T, T>,
-) -> DMatrix<f64> {
+pub fn euclidean_distances<T: Real, D: na::Dim>(
+    x: &MatrixView<T, D, D>,
+    y: &MatrixView<T, D, D>,
+) -> DMatrix<T> {
     let nrows = x.nrows();
     let ncols = x.ncols();
     let mut result = DMatrix::zeros(nrows, 1);
     for i in 0..nrows {
-        let mut dists: f64 = 0.;
+        let mut dists: T = T::zero();
         for j in 0..ncols {
-            dists += (x[(i, j)] - y[(i, j)]).pow(2);
+            dists += (x[(i, j)] - y[(i, j)]).powi(2);
         }
         result[(i, 0)] = dists.sqrt();
     }
     result
 }
 
-pub fn vector_projected_distances<'a, T: na::Dim>(
-    x: &'a MatrixView<f64, T, T>,
-    y: &'a MatrixView<f64, T, T>,
+pub fn vector_projected_distances<T: Real, D: na::Dim>(
+    x: &MatrixView<T, D, D>,
+    y: &MatrixView<T, D, D>,
     col: usize,
-) -> DMatrix<f64> {
+) -> DMatrix<T> {
     let ncols = 1;
     let nrows = x.nrows();
     let mut result = DMatrix::zeros(nrows, ncols);
@@ -118,7 +121,7 @@ pub fn vector_projected_distances<'a, T: na::Dim>(
     result
 }
 
-pub fn point_projected_distances<'a, T: na::Dim>(x: &'a MatrixView<f64, T, T>) -> DMatrix<f64> {
+pub fn point_projected_distances<T: Real, D: na::Dim>(x: &MatrixView<T, D, D>) -> DMatrix<T> {
     let ncols = 2;
     let nrows = x.nrows();
     let mut result = DMatrix::zeros(nrows, ncols);
@@ -129,84 +132,140 @@ pub fn point_projected_distances<'a, T: na::Dim>(x: &'a MatrixView<f64, T, T>) -
     result
 }
 
-pub fn point_to_centroid_distances<'a, T: na::Dim>(x: &'a MatrixView<f64, T, T>) -> DMatrix<f64> {
+pub fn point_to_centroid_distances<T: Real, D: na::Dim>(x: &MatrixView<T, D, D>) -> DMatrix<T> {
     let ncols = 1;
     let nrows = x.nrows();
     let mut result = DMatrix::zeros(nrows, ncols);
     for i in 0..nrows {
-        let mut dists: f64 = 0.;
+        let mut dists: T = T::zero();
         for j in 0..x.ncols() {
-            dists += x[(i, j)].pow(2);
+            dists += x[(i, j)].powi(2);
         }
         result[(i, 0)] = dists.sqrt();
     }
     result
 }
 
-pub fn anisotropy_planarity_linearity<'a, T: na::Dim>(
-    x: &'a MatrixView<f64, T, T>,
-    y: &'a MatrixView<f64, T, T>,
+pub fn anisotropy_planarity_linearity<T: Real, D: na::Dim>(
+    x: &MatrixView<T, D, D>,
+    y: &MatrixView<T, D, D>,
     col1: usize,
     col2: usize,
-) -> DMatrix<f64> {
+) -> DMatrix<T> {
     let nrows = x.nrows();
     let mut result = DMatrix::zeros(nrows, 1);
     for i in 0..nrows {
-        let x_diff = (x[(i, col1)] - x[(i, col2)]) / (x[(i, 0)] + EPSILON);
-        let y_diff = (y[(i, col1)] - y[(i, col2)]) / (y[(i, 0)] + EPSILON);
+        let x_diff = (x[(i, col1)] - x[(i, col2)]) / (x[(i, 0)] + epsilon());
+        let y_diff = (y[(i, col1)] - y[(i, col2)]) / (y[(i, 0)] + epsilon());
         result[(i, 0)] = relative_difference(x_diff, y_diff);
     }
     result
 }
 
-pub fn surface_variation<'a, T: na::Dim>(
-    x: &'a MatrixView<f64, T, T>,
-    y: &'a MatrixView<f64, T, T>,
-) -> DMatrix<f64> {
+pub fn surface_variation<T: Real, D: na::Dim>(
+    x: &MatrixView<T, D, D>,
+    y: &MatrixView<T, D, D>,
+) -> DMatrix<T> {
     let nrows = x.nrows();
     let mut result = DMatrix::zeros(nrows, 1);
     for i in 0..nrows {
-        let x_sum: f64 = x.row(i).sum();
-        let y_sum: f64 = y.row(i).sum();
-        result[(i, 0)] =
-            relative_difference(x[(i, 2)] / (x_sum + EPSILON), y[(i, 2)] / (y_sum + EPSILON));
+        let x_sum: T = x.row(i).sum();
+        let y_sum: T = y.row(i).sum();
+        result[(i, 0)] = relative_difference(
+            x[(i, 2)] / (x_sum + epsilon()),
+            y[(i, 2)] / (y_sum + epsilon()),
+        );
     }
     result
 }
 
-pub fn sphericity<'a, T: na::Dim>(
-    x: &'a MatrixView<f64, T, T>,
-    y: &'a MatrixView<f64, T, T>,
-) -> DMatrix<f64> {
+pub fn sphericity<T: Real, D: na::Dim>(
+    x: &MatrixView<T, D, D>,
+    y: &MatrixView<T, D, D>,
+) -> DMatrix<T> {
     let nrows = x.nrows();
     let mut result = DMatrix::zeros(nrows, 1);
     for i in 0..nrows {
         result[(i, 0)] = relative_difference(
-            x[(i, 2)] / (x[(i, 0)] + EPSILON),
-            y[(i, 2)] / (y[(i, 0)] + EPSILON),
+            x[(i, 2)] / (x[(i, 0)] + epsilon()),
+            y[(i, 2)] / (y[(i, 0)] + epsilon()),
         );
     }
     result
 }
 
-pub fn angular_similarity<'a, T: na::Dim>(x: &'a MatrixView<f64, T, T>) -> DMatrix<f64> {
+pub fn angular_similarity<T: Real, D: na::Dim>(x: &MatrixView<T, D, D>) -> DMatrix<T> {
     let nrows = x.nrows();
     let mut result = DMatrix::zeros(nrows, 1);
     for i in 0..nrows {
         let numerator = x[(i, 1)];
         let (mut a, mut b, mut c) = (x[(i, 0)], x[(i, 1)], x[(i, 2)]);
-        (a, b, c) = (a.pow(2), b.pow(2), c.pow(2));
-        let denominator: f64 = (a + b + c).sqrt() + EPSILON;
-        result[(i, 0)] = 1. - 2. * acos((numerator / denominator).abs()) / PI;
+        (a, b, c) = (a.powi(2), b.powi(2), c.powi(2));
+        let denominator: T = (a + b + c).sqrt() + epsilon();
+        result[(i, 0)] =
+            T::one() - T::cast(2.) * (numerator / denominator).abs().acos_libm() / T::pi();
     }
     result
 }
 
-pub fn parallelity<'a, T: na::Dim>(x: &'a MatrixView<f64, T, T>, col: usize) -> DMatrix<f64> {
+pub fn parallelity<T: Real, D: na::Dim>(x: &MatrixView<T, D, D>, col: usize) -> DMatrix<T> {
     let nrows = x.nrows();
     let mut result = DMatrix::zeros(nrows, 1);
     for (i, num) in x.column(col).iter().enumerate() {
-        result[i] = 1. - *num;
+        result[i] = T::one() - *num;
+    }
+    result
+}
+
+// Distances from the points of x to the planes through the points of y with unit normals
+pub fn point_to_plane_distances<T: Real, D: na::Dim>(
+    x: &MatrixView<T, D, D>,
+    y: &MatrixView<T, D, D>,
+    normals: &MatrixView<T, D, D>,
+) -> DMatrix<T> {
+    let nrows = x.nrows();
+    let mut result = DMatrix::zeros(nrows, 1);
+    for i in 0..nrows {
+        let mut dot: T = T::zero();
+        for j in 0..x.ncols() {
+            dot += (x[(i, j)] - y[(i, j)]) * normals[(i, j)];
+        }
+        result[(i, 0)] = dot.abs();
+    }
+    result
+}
+
+// Differences between the root mean square distances of the neighborhoods of x and y to the
+// plane through the mean of x normal to the axis col, from their projected means and variances,
+// the projected mean of x being zero
+pub fn plane_rms_differences<T: Real, D: na::Dim>(
+    variances_x: &MatrixView<T, D, D>,
+    means_y: &MatrixView<T, D, D>,
+    variances_y: &MatrixView<T, D, D>,
+    col: usize,
+) -> DMatrix<T> {
+    let nrows = variances_x.nrows();
+    let mut result = DMatrix::zeros(nrows, 1);
+    for i in 0..nrows {
+        let rms_x = variances_x[(i, col)].sqrt();
+        let rms_y = (variances_y[(i, col)] + means_y[(i, col)].powi(2)).sqrt();
+        result[(i, 0)] = (rms_y - rms_x).abs();
+    }
+    result
+}
+
+// Similarity of the angle between the normals of x and the axis col, 1 when parallel and 0
+// when orthogonal, regardless of the orientation of the normals
+pub fn normal_angle_similarity<T: Real, D: na::Dim>(
+    x: &MatrixView<T, D, D>,
+    col: usize,
+) -> DMatrix<T> {
+    let nrows = x.nrows();
+    let mut result = DMatrix::zeros(nrows, 1);
+    for i in 0..nrows {
+        let norm = x.row(i).norm() + epsilon();
+        let cosine = (x[(i, col)] / norm).abs().min(T::one());
+        result[(i, 0)] = T::one() - T::cast(2.) * cosine.acos_libm() / T::pi();
     }
     result
 }
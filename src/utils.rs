@@ -1,22 +1,19 @@
 use na::{Const, DMatrix, Dyn, Matrix, Scalar, VecStorage};
+use std::collections::HashMap;
 use std::ops::AddAssign;
 
-pub fn print_if_verbose<'a>(string: &'a str, verbose: &'a bool) {
-    if *verbose {
-        println!("{}", string);
-    }
-}
-
-pub fn slice_from_knn_indices<'a>(
-    points: &'a Vec<[f64; 3]>,
-    colors: &'a Vec<[u8; 3]>,
-    knn_indices: &'a Vec<usize>,
-    search_size: usize,
+// One row per neighbor, so neighborhoods of any size are gathered. search_size is ignored,
+// it is only kept so that existing callers still compile
+#[deprecated(note = "The feature kernel gathers neighborhoods into fixed-size vectors instead")]
+pub fn slice_from_knn_indices(
+    points: &[[f64; 3]],
+    colors: &[[u8; 3]],
+    knn_indices: &[usize],
+    _search_size: usize,
 ) -> (DMatrix<f64>, DMatrix<f64>) {
-    let nrows = search_size;
-    let ncols = points[0].len();
-    let mut selected_points = DMatrix::zeros(nrows, ncols);
-    let mut selected_colors = DMatrix::zeros(nrows, ncols);
+    let nrows = knn_indices.len();
+    let mut selected_points = DMatrix::zeros(nrows, 3);
+    let mut selected_colors = DMatrix::zeros(nrows, 3);
     for (i, &nbr_idx) in knn_indices.iter().enumerate() {
         selected_points.row_mut(i).copy_from_slice(&points[nbr_idx]);
         selected_colors
@@ -26,7 +23,8 @@ pub fn slice_from_knn_indices<'a>(
     (selected_points, selected_colors)
 }
 
-pub fn concatenate_columns<'a, T>(mat1: &'a DMatrix<T>, mat2: &'a DMatrix<T>) -> DMatrix<T>
+#[deprecated(note = "The feature kernel no longer concatenates matrices")]
+pub fn concatenate_columns<T>(mat1: &DMatrix<T>, mat2: &DMatrix<T>) -> DMatrix<T>
 where
     T: Scalar + Copy + AddAssign + num_traits::identities::Zero,
 {
@@ -38,16 +36,35 @@ where
     let mut result = DMatrix::zeros(mat1.nrows(), mat1.ncols() + mat2.ncols());
     result
         .view_mut((0, 0), (mat1.nrows(), mat1.ncols()))
-        .copy_from(&mat1);
+        .copy_from(mat1);
     result
         .view_mut((0, mat1.ncols()), (mat2.nrows(), mat2.ncols()))
-        .copy_from(&mat2);
+        .copy_from(mat2);
+    result
+}
+
+pub fn concatenate_rows<T>(matrices: &[DMatrix<T>]) -> DMatrix<T>
+where
+    T: Scalar + Copy + AddAssign + num_traits::identities::Zero,
+{
+    let ncols = matrices.first().map_or(0, |m| m.ncols());
+    assert!(
+        matrices.iter().all(|m| m.ncols() == ncols),
+        "Matrices must have the same number of columns for concatenation."
+    );
+    let nrows = matrices.iter().map(|m| m.nrows()).sum();
+    let mut result = DMatrix::zeros(nrows, ncols);
+    let mut start = 0;
+    for m in matrices {
+        result.view_mut((start, 0), (m.nrows(), ncols)).copy_from(m);
+        start += m.nrows();
+    }
     result
 }
 
-pub fn subtract_row_from_matrix<'a>(
-    matrix: &'a DMatrix<f64>,
-    row_vec: &'a Matrix<f64, Const<1>, Dyn, VecStorage<f64, Const<1>, Dyn>>,
+pub fn subtract_row_from_matrix(
+    matrix: &DMatrix<f64>,
+    row_vec: &Matrix<f64, Const<1>, Dyn, VecStorage<f64, Const<1>, Dyn>>,
 ) -> DMatrix<f64> {
     assert_eq!(row_vec.nrows(), 1, "row_vec must be a single-row vector.");
     assert_eq!(
@@ -59,3 +76,48 @@ pub fn subtract_row_from_matrix<'a>(
     new_matrix.row_iter_mut().for_each(|mut row| row -= row_vec);
     new_matrix
 }
+
+// Small deterministic generator (SplitMix64), so sampling is reproducible from a seed
+pub struct SplitMix64(u64);
+
+impl SplitMix64 {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    // Uniform in [0, n)
+    pub fn next_below(&mut self, n: usize) -> usize {
+        ((self.next_u64() as u128 * n as u128) >> 64) as usize
+    }
+}
+
+// Uniform sample of sample_size distinct indices below n, in increasing order. Partial
+// Fisher-Yates shuffle over 0..n, where only the displaced entries are stored, so memory
+// follows sample_size rather than n
+pub fn sample_indices(n: usize, sample_size: usize, seed: u64) -> Vec<usize> {
+    let mut rng = SplitMix64::new(seed);
+    let sample_size = sample_size.min(n);
+    let mut displaced: HashMap<usize, usize> = HashMap::with_capacity(sample_size);
+    let mut indices = Vec::with_capacity(sample_size);
+    for i in 0..sample_size {
+        let j = i + rng.next_below(n - i);
+        // Swap entries i and j, entry i is never read again
+        let entry_i = displaced.remove(&i).unwrap_or(i);
+        let entry_j = if j == i {
+            entry_i
+        } else {
+            displaced.insert(j, entry_i).unwrap_or(j)
+        };
+        indices.push(entry_j);
+    }
+    indices.sort_unstable();
+    indices
+}
@@ -0,0 +1,81 @@
+use pointpca2_rs::pca::NeighborhoodClassCounts;
+use pointpca2_rs::predictors::Attributes;
+use pointpca2_rs::{compute_pointpca2_with_config, PointPca2Config, PointPca2Result};
+
+mod common;
+
+use common::{voxelized_cloud, Surface};
+
+fn run(attributes: Attributes, with_colors: bool) -> PointPca2Result {
+    let (points_a, mut colors_a) = voxelized_cloud(0.);
+    let (points_b, mut colors_b) = Surface {
+        bump: 1.,
+        color_shift: 20,
+        ..Default::default()
+    }
+    .cloud();
+    if !with_colors {
+        colors_a.clear();
+        colors_b.clear();
+    }
+    let config = PointPca2Config {
+        attributes,
+        ..PointPca2Config::new(27)
+    };
+    compute_pointpca2_with_config(points_a, colors_a, points_b, colors_b, &config).unwrap()
+}
+
+// Predictors of the full run whose names are among those of the subset
+fn assert_subset_of_full(subset: &PointPca2Result, full: &PointPca2Result) {
+    assert!(!subset.predictor_names.is_empty());
+    for (j, name) in subset.predictor_names.iter().enumerate() {
+        let i = full.predictor_names.iter().position(|n| n == name).unwrap();
+        assert_eq!(subset.predictors[j], full.predictors[i], "{}", name);
+    }
+}
+
+#[test]
+fn geometry_only_matches_geometry_predictors() {
+    let full = run(Attributes::Both, true);
+    let geometry = run(Attributes::Geometry, true);
+    assert!(geometry
+        .predictor_names
+        .iter()
+        .all(|n| !n.starts_with("color")));
+    assert_subset_of_full(&geometry, &full);
+    // Colors are not needed to compare the geometry
+    let colorless = run(Attributes::Geometry, false);
+    assert_eq!(colorless.predictors, geometry.predictors);
+}
+
+#[test]
+fn texture_only_matches_texture_predictors() {
+    let full = run(Attributes::Both, true);
+    let texture = run(Attributes::Texture, true);
+    assert!(texture
+        .predictor_names
+        .iter()
+        .all(|n| n.starts_with("color")));
+    assert_subset_of_full(&texture, &full);
+    // Together the two modes cover every predictor
+    let geometry = run(Attributes::Geometry, true);
+    assert_eq!(
+        texture.predictor_names.len() + geometry.predictor_names.len(),
+        full.predictor_names.len()
+    );
+}
+
+#[test]
+fn texture_only_keeps_neighborhoods_unclassified() {
+    let full = run(Attributes::Both, true);
+    let texture = run(Attributes::Texture, true);
+    let counts = full.neighborhood_classes;
+    let points = counts.full_rank + counts.planar + counts.linear + counts.degenerate;
+    assert_eq!(
+        texture.neighborhood_classes,
+        NeighborhoodClassCounts {
+            unclassified: points,
+            ..Default::default()
+        }
+    );
+}
@@ -0,0 +1,80 @@
+// Fixtures shared by the integration tests. Each test crate uses a subset of them
+#![allow(dead_code)]
+
+pub type Cloud = (Vec<[f64; 3]>, Vec<[u8; 3]>);
+
+// Wavy surface sampled on a size x size grid, with colors following the grid coordinates
+#[derive(Clone, Copy, Debug)]
+pub struct Surface {
+    pub size: usize,
+    // Added to the height before rounding
+    pub offset: f64,
+    // Rounds heights to integers, so that many neighbors are equidistant
+    pub voxelized: bool,
+    // Added to every third point of the half x < size / 2, after rounding
+    pub bump: f64,
+    // Added to the red channel
+    pub color_shift: u8,
+    // Added to every coordinate
+    pub origin: f64,
+}
+
+impl Surface {
+    pub fn new(offset: f64) -> Self {
+        Self {
+            offset,
+            ..Default::default()
+        }
+    }
+
+    pub fn cloud(&self) -> Cloud {
+        let mut points = Vec::new();
+        let mut colors = Vec::new();
+        let step = 240 / self.size;
+        for x in 0..self.size {
+            for y in 0..self.size {
+                let mut z = (x as f64 / 4.).sin() * 3. + (y as f64 / 5.).cos() * 2. + self.offset;
+                if self.voxelized {
+                    z = z.round();
+                }
+                if x < self.size / 2 {
+                    z += self.bump * ((x + y) % 3) as f64;
+                }
+                points.push([x as f64, y as f64, z].map(|c| c + self.origin));
+                colors.push([
+                    ((x * step) as u8).wrapping_add(self.color_shift),
+                    (y * step) as u8,
+                    ((x + y) * step / 2) as u8,
+                ]);
+            }
+        }
+        (points, colors)
+    }
+}
+
+impl Default for Surface {
+    fn default() -> Self {
+        Self {
+            size: 24,
+            offset: 0.,
+            voxelized: true,
+            bump: 0.,
+            color_shift: 0,
+            origin: 0.,
+        }
+    }
+}
+
+// Default voxelized surface at height offset
+pub fn voxelized_cloud(offset: f64) -> Cloud {
+    Surface::new(offset).cloud()
+}
+
+// Points uniformly distributed in the cube [0, extent)^3
+pub fn random_points(seed: u64, npoints: usize, extent: f64) -> Vec<[f64; 3]> {
+    let mut rng = pointpca2_rs::utils::SplitMix64::new(seed);
+    let mut uniform = || (rng.next_u64() >> 11) as f64 / (1u64 << 53) as f64 * extent;
+    (0..npoints)
+        .map(|_| [uniform(), uniform(), uniform()])
+        .collect()
+}
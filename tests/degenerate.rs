@@ -0,0 +1,121 @@
+use pointpca2_rs::pca::{DegeneratePolicy, NeighborhoodClass, NeighborhoodClassCounts};
+use pointpca2_rs::{
+    compute_pointpca2_with_config, PointPca2Config, PointPca2Error, PointPca2Result,
+};
+
+mod common;
+
+use common::{random_points, Cloud};
+
+const K: usize = 8;
+
+// Full-rank blob of 60 points, a 10 x 10 planar grid far below it and a line of 12 points
+// whose 16 nearest neighbors reach the blob
+struct Parts {
+    blob: Vec<[f64; 3]>,
+    grid: Vec<[f64; 3]>,
+    line: Vec<[f64; 3]>,
+}
+
+impl Parts {
+    fn new() -> Self {
+        let blob = random_points(3, 60, 4.);
+        let grid = (0..100)
+            .map(|i| [(i % 10) as f64, (i / 10) as f64, -500.])
+            .collect();
+        let line = (0..12).map(|i| [20. + i as f64, 2., 2.]).collect();
+        Self { blob, grid, line }
+    }
+}
+
+fn colored(parts: &[&[[f64; 3]]], seed: usize) -> Cloud {
+    let points = parts.concat();
+    let colors = (0..points.len())
+        .map(|i| [(i * 7 + seed) as u8, (i * 13) as u8, (i * 29 + seed) as u8])
+        .collect();
+    (points, colors)
+}
+
+// Runs the reference made of the given parts against the whole distorted cloud
+fn run(parts: &[&[[f64; 3]]], policy: DegeneratePolicy) -> Result<PointPca2Result, PointPca2Error> {
+    let all = Parts::new();
+    let (points_a, colors_a) = colored(parts, 0);
+    let (points_b, colors_b) = colored(&[&all.blob, &all.grid, &all.line], 50);
+    let config = PointPca2Config {
+        degenerate_policy: policy,
+        ..PointPca2Config::new(K)
+    };
+    compute_pointpca2_with_config(points_a, colors_a, points_b, colors_b, &config)
+}
+
+fn counts(full_rank: usize, planar: usize, linear: usize) -> NeighborhoodClassCounts {
+    NeighborhoodClassCounts {
+        full_rank,
+        planar,
+        linear,
+        degenerate: 0,
+        unclassified: 0,
+    }
+}
+
+#[test]
+fn neighborhood_classes_are_counted() {
+    let parts = Parts::new();
+    let all = [&parts.blob[..], &parts.grid, &parts.line];
+    let result = run(&all, DegeneratePolicy::Keep).unwrap();
+    assert_eq!(result.neighborhood_classes, counts(60, 100, 12));
+}
+
+#[test]
+fn skipped_neighborhoods_are_excluded_from_pooling() {
+    let parts = Parts::new();
+    let all = [&parts.blob[..], &parts.grid, &parts.line];
+    let cases = [
+        (
+            NeighborhoodClass::Linear,
+            vec![&parts.blob[..], &parts.grid],
+        ),
+        (NeighborhoodClass::Planar, vec![&parts.blob[..]]),
+    ];
+    for (from, kept) in cases {
+        let skipped = run(&all, DegeneratePolicy::Skip { from }).unwrap();
+        // Skipped points are still counted in their class
+        assert_eq!(skipped.neighborhood_classes, counts(60, 100, 12));
+        // Same pool as a reference without the skipped points
+        let expected = run(&kept, DegeneratePolicy::Keep).unwrap();
+        for (x, y) in skipped.predictors.iter().zip(expected.predictors.iter()) {
+            assert!(
+                (x - y).abs() <= 1e-12 * y.abs().max(1.),
+                "{:?}: {} {}",
+                from,
+                x,
+                y
+            );
+        }
+    }
+    let skipped = run(
+        &all,
+        DegeneratePolicy::Skip {
+            from: NeighborhoodClass::FullRank,
+        },
+    );
+    assert_eq!(
+        skipped.err(),
+        Some(PointPca2Error::AllNeighborhoodsSkipped { points: 172 })
+    );
+}
+
+#[test]
+fn expanded_neighborhoods_improve_their_class() {
+    let parts = Parts::new();
+    let all = [&parts.blob[..], &parts.grid, &parts.line];
+    let policy = DegeneratePolicy::Expand {
+        from: NeighborhoodClass::Planar,
+        max_k: 64,
+    };
+    let expanded = run(&all, policy).unwrap();
+    // The line reaches the blob, the grid has more than max_k points
+    assert_eq!(expanded.neighborhood_classes, counts(72, 100, 0));
+    let kept = run(&all, DegeneratePolicy::Keep).unwrap();
+    assert_ne!(expanded.predictors, kept.predictors);
+}
@@ -0,0 +1,54 @@
+use pointpca2_rs::neighbor_index::{self, IndexBackend};
+use pointpca2_rs::{compute_pointpca2, knn_search};
+
+mod common;
+
+use common::voxelized_cloud;
+
+fn run_with_threads(num_threads: usize) -> Vec<u64> {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(num_threads)
+        .build()
+        .unwrap();
+    let (points_a, colors_a) = voxelized_cloud(0.);
+    let (points_b, colors_b) = voxelized_cloud(0.4);
+    let predictors =
+        pool.install(|| compute_pointpca2(points_a, colors_a, points_b, colors_b, 27, false));
+    predictors.iter().map(|x| x.to_bits()).collect()
+}
+
+#[test]
+fn predictors_are_bitwise_identical_across_thread_counts() {
+    let reference = run_with_threads(1);
+    for num_threads in [2, 3, 8] {
+        assert_eq!(reference, run_with_threads(num_threads));
+    }
+}
+
+#[test]
+fn neighbors_are_ordered_by_distance_then_index() {
+    let (points, _) = voxelized_cloud(0.);
+    let backends = [
+        IndexBackend::KdTree,
+        IndexBackend::VoxelGrid { cell_size: None },
+        IndexBackend::VoxelGrid {
+            cell_size: Some(2.5),
+        },
+        IndexBackend::ApproximateKdTree {
+            max_checks: usize::MAX,
+        },
+    ];
+    let reference = neighbor_index::build_index(&points, &IndexBackend::BruteForce);
+    for backend in backends {
+        let index = neighbor_index::build_index(&points, &backend);
+        for point in points.iter().step_by(7) {
+            let expected = knn_search::nearest_n(reference.as_ref(), point, 27);
+            assert_eq!(expected, knn_search::nearest_n(index.as_ref(), point, 27));
+            let expected = knn_search::within_radius(reference.as_ref(), point, 3.);
+            assert_eq!(
+                expected,
+                knn_search::within_radius(index.as_ref(), point, 3.)
+            );
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use pointpca2_rs::execution::{CancellationToken, Threads};
+use pointpca2_rs::features;
+use pointpca2_rs::knn_search::Neighborhood;
+use pointpca2_rs::precision::Precision;
+use pointpca2_rs::predictors::PredictorRegistry;
+use pointpca2_rs::progress::{ProgressSink, Stage};
+use pointpca2_rs::{compute_pointpca2_with_config, PointPca2Config, PointPca2Error};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+mod common;
+
+use common::{voxelized_cloud, Surface};
+
+fn run(config: &PointPca2Config) -> Result<Vec<u64>, PointPca2Error> {
+    let (points_a, colors_a) = voxelized_cloud(0.);
+    let (points_b, colors_b) = voxelized_cloud(0.4);
+    let result = compute_pointpca2_with_config(points_a, colors_a, points_b, colors_b, config)?;
+    Ok(result.predictors.iter().map(|x| x.to_bits()).collect())
+}
+
+#[test]
+fn dedicated_pools_match_global_pool() {
+    let reference = run(&PointPca2Config::new(27)).unwrap();
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(3)
+        .build()
+        .unwrap();
+    for threads in [Threads::Count(2), Threads::Pool(Arc::new(pool))] {
+        let config = PointPca2Config {
+            threads,
+            ..PointPca2Config::new(27)
+        };
+        assert_eq!(reference, run(&config).unwrap());
+    }
+}
+
+// Cancels the run once the given number of points is processed, recording the progress
+#[derive(Debug)]
+struct CancelAfter {
+    points: usize,
+    cancellation: CancellationToken,
+    events: Mutex<Vec<String>>,
+}
+
+impl ProgressSink for CancelAfter {
+    fn stage_started(&self, stage: Stage) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(format!("started {}", stage));
+    }
+
+    fn stage_finished(&self, stage: Stage, _elapsed: Duration) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(format!("finished {}", stage));
+    }
+
+    fn points_processed(&self, processed: usize, total: usize) {
+        let mut events = self.events.lock().unwrap();
+        events.push(format!("{} of {} points", processed, total));
+        if processed >= self.points {
+            self.cancellation.cancel();
+        }
+    }
+}
+
+#[test]
+fn cancelled_runs_return_an_error() {
+    let cancellation = CancellationToken::new();
+    // Chunks of exactly 1024 points, the progress interval, so that the run is cancelled
+    // when the first chunk completes and only the checks between chunks can stop it
+    let registry = PredictorRegistry::builtin();
+    let bytes_per_point =
+        features::bytes_per_point(&Neighborhood::Knn(27), registry.dimension(), Precision::F64);
+    let progress = Arc::new(CancelAfter {
+        points: 1024,
+        cancellation: cancellation.clone(),
+        events: Mutex::new(Vec::new()),
+    });
+    let config = PointPca2Config {
+        cancellation: Some(cancellation.clone()),
+        memory_budget: Some(1024 * bytes_per_point),
+        progress: progress.clone(),
+        ..PointPca2Config::new(27)
+    };
+    let (points_a, colors_a) = Surface {
+        size: 64,
+        ..Default::default()
+    }
+    .cloud();
+    let (points_b, colors_b) = Surface {
+        size: 64,
+        ..Surface::new(0.4)
+    }
+    .cloud();
+    let result = compute_pointpca2_with_config(points_a, colors_a, points_b, colors_b, &config);
+    assert_eq!(result.err(), Some(PointPca2Error::Cancelled));
+    // The first chunk is neither pooled nor followed by another one
+    let events = progress.events.lock().unwrap();
+    let features = events
+        .iter()
+        .skip_while(|event| *event != "started Computing local features");
+    assert_eq!(
+        features.collect::<Vec<_>>(),
+        [
+            "started Computing local features",
+            "1024 of 4096 points",
+            "finished Computing local features"
+        ]
+    );
+    // A token cancelled before the call stops the run at once
+    assert_eq!(run(&config), Err(PointPca2Error::Cancelled));
+}
@@ -0,0 +1,134 @@
+use na::{DMatrix, RowDVector};
+use nalgebra as na;
+use pointpca2_rs::neighbor_index::{self, IndexBackend};
+use pointpca2_rs::{features, knn_search, pca, utils, PointPca2Config};
+
+mod common;
+
+use common::{Cloud, Surface};
+
+const K: usize = 27;
+
+fn rows_of(
+    points: &[[f64; 3]],
+    colors: &[[u8; 3]],
+    indices: &[usize],
+) -> (DMatrix<f64>, DMatrix<f64>) {
+    let points = DMatrix::from_fn(indices.len(), 3, |i, j| points[indices[i]][j]);
+    let colors = DMatrix::from_fn(indices.len(), 3, |i, j| colors[indices[i]][j] as f64);
+    (points, colors)
+}
+
+fn centered(matrix: &DMatrix<f64>, mean: &RowDVector<f64>) -> DMatrix<f64> {
+    let mut matrix = matrix.clone();
+    matrix.row_iter_mut().for_each(|mut row| row -= mean);
+    matrix
+}
+
+// Local features of one point from DMatrix operations, as computed before the per-point
+// kernel worked on fixed-size vectors
+fn reference_features(
+    a: &Cloud,
+    b: &Cloud,
+    indices_a: &[usize],
+    indices_b: &[usize],
+) -> RowDVector<f64> {
+    let (points_a, colors_a) = rows_of(&a.0, &a.1, indices_a);
+    let (points_b, colors_b) = rows_of(&b.0, &b.1, indices_b);
+    let eigenvectors_a = DMatrix::from_column_slice(3, 3, pca::compute_pca3(&points_a).as_slice());
+    let points_a_mean = points_a.row_mean();
+    let projection_a_to_a = centered(&points_a, &points_a_mean) * &eigenvectors_a;
+    let projection_b_to_a = centered(&points_b, &points_a_mean) * &eigenvectors_a;
+    let attributes_a = DMatrix::from_fn(points_a.nrows(), 6, |i, j| {
+        if j < 3 {
+            projection_a_to_a[(i, j)]
+        } else {
+            colors_a[(i, j - 3)]
+        }
+    });
+    let attributes_b = DMatrix::from_fn(points_b.nrows(), 6, |i, j| {
+        if j < 3 {
+            projection_b_to_a[(i, j)]
+        } else {
+            colors_b[(i, j - 3)]
+        }
+    });
+    let (mean_a, mean_b) = (attributes_a.row_mean(), attributes_b.row_mean());
+    let deviation_a = centered(&attributes_a, &mean_a);
+    let deviation_b = centered(&attributes_b, &mean_b);
+    let variance_a = deviation_a.map(|x| x.powi(2)).row_mean();
+    let variance_b = deviation_b.map(|x| x.powi(2)).row_mean();
+    let common_rows = deviation_a.nrows().min(deviation_b.nrows());
+    let covariance_ab = deviation_a
+        .rows(0, common_rows)
+        .component_mul(&deviation_b.rows(0, common_rows))
+        .row_mean();
+    let eigenvectors_b = pca::compute_pca3(&projection_b_to_a).transpose();
+    let mut row = RowDVector::zeros(42);
+    row.columns_mut(0, 3).copy_from(&projection_a_to_a.row(0));
+    row.columns_mut(3, 3).copy_from(&projection_b_to_a.row(0));
+    row.columns_mut(6, 3).copy_from(&mean_a.columns(3, 3));
+    row.columns_mut(9, 6).copy_from(&mean_b);
+    row.columns_mut(15, 6).copy_from(&variance_a);
+    row.columns_mut(21, 6).copy_from(&variance_b);
+    row.columns_mut(27, 6).copy_from(&covariance_ab);
+    for axis in 0..3 {
+        row.columns_mut(33 + 3 * axis, 3)
+            .copy_from(&eigenvectors_b.row(axis));
+    }
+    row
+}
+
+#[test]
+fn kernel_matches_the_dmatrix_reference() {
+    let a = Surface::default().cloud();
+    let b = Surface {
+        offset: 0.4,
+        bump: 0.3,
+        color_shift: 9,
+        ..Default::default()
+    }
+    .cloud();
+    let (local_features, _) = features::compute_features_with_config(
+        a.0.clone(),
+        a.1.clone(),
+        b.0.clone(),
+        b.1.clone(),
+        &PointPca2Config::new(K),
+    );
+    let index_a = neighbor_index::build_index(&a.0, &IndexBackend::KdTree);
+    let index_b = neighbor_index::build_index(&b.0, &IndexBackend::KdTree);
+    for (i, query) in a.0.iter().enumerate() {
+        let indices_a = knn_search::nearest_n(index_a.as_ref(), query, K);
+        let indices_b = knn_search::nearest_n(index_b.as_ref(), query, K);
+        let expected = reference_features(&a, &b, &indices_a, &indices_b);
+        let actual = local_features.row(i);
+        for (j, (x, y)) in actual.iter().zip(expected.iter()).enumerate() {
+            assert_eq!(
+                x.to_bits(),
+                y.to_bits(),
+                "point {}, column {}: {} {}",
+                i,
+                j,
+                x,
+                y
+            );
+        }
+    }
+}
+
+#[test]
+#[allow(deprecated)]
+fn deprecated_helpers_gather_neighborhoods_of_any_size() {
+    let (points, colors) = Surface::default().cloud();
+    for indices in [vec![5], vec![3, 1, 4, 1, 5, 9, 2, 6]] {
+        let (sliced_points, sliced_colors) =
+            utils::slice_from_knn_indices(&points, &colors, &indices, K);
+        let (expected_points, expected_colors) = rows_of(&points, &colors, &indices);
+        assert_eq!(sliced_points, expected_points);
+        assert_eq!(sliced_colors, expected_colors);
+        let concatenated = utils::concatenate_columns(&sliced_points, &sliced_colors);
+        assert_eq!(concatenated.shape(), (indices.len(), 6));
+        assert_eq!(concatenated.columns(3, 3), sliced_colors);
+    }
+}
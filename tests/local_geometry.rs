@@ -0,0 +1,74 @@
+use pointpca2_rs::local_geometry::{self, LocalGeometryConfig, NormalOrientation, PointGeometry};
+
+// Fibonacci sampling of a sphere of the given radius centered on the origin
+fn sphere(npoints: usize, radius: f64) -> Vec<[f64; 3]> {
+    let golden_angle = std::f64::consts::PI * (3. - 5f64.sqrt());
+    (0..npoints)
+        .map(|i| {
+            let z = 1. - 2. * (i as f64 + 0.5) / npoints as f64;
+            let r = (1. - z * z).sqrt();
+            let theta = golden_angle * i as f64;
+            [r * theta.cos(), r * theta.sin(), z].map(|x| x * radius)
+        })
+        .collect()
+}
+
+fn geometry(points: &[[f64; 3]], normal_orientation: NormalOrientation) -> Vec<PointGeometry> {
+    let config = LocalGeometryConfig {
+        normal_orientation,
+        ..LocalGeometryConfig::new(16)
+    };
+    local_geometry::compute_local_geometry(points, &config)
+}
+
+fn dot(a: &[f64; 3], b: &[f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn assert_normal_is_third_axis(geometry: &[PointGeometry]) {
+    for g in geometry {
+        let axis = g.eigenvectors.column(2);
+        assert_eq!(g.normal, [axis[0], axis[1], axis[2]]);
+    }
+}
+
+#[test]
+fn propagated_normals_point_outwards_on_a_closed_surface() {
+    let points = sphere(2000, 10.);
+    let unoriented = geometry(&points, NormalOrientation::None);
+    // The PCA sign alone leaves normals on both sides of the surface
+    let inwards = unoriented
+        .iter()
+        .zip(&points)
+        .filter(|(g, p)| dot(&g.normal, p) < 0.)
+        .count();
+    assert!(inwards > 0);
+    let oriented = geometry(&points, NormalOrientation::Propagate);
+    assert_normal_is_third_axis(&oriented);
+    for (g, point) in oriented.iter().zip(&points) {
+        // Radial normals, as the sphere is locally flat at this sampling
+        assert!(
+            dot(&g.normal, point) > 0.9 * 10.,
+            "{:?} at {:?}",
+            g.normal,
+            point
+        );
+    }
+}
+
+#[test]
+fn viewpoint_normals_face_the_viewpoint() {
+    let points = sphere(2000, 10.);
+    let viewpoint = [3., -40., 25.];
+    let oriented = geometry(&points, NormalOrientation::Viewpoint(viewpoint));
+    assert_normal_is_third_axis(&oriented);
+    for (g, point) in oriented.iter().zip(&points) {
+        let to_viewpoint = [0, 1, 2].map(|j| viewpoint[j] - point[j]);
+        assert!(
+            dot(&g.normal, &to_viewpoint) >= 0.,
+            "{:?} at {:?}",
+            g.normal,
+            point
+        );
+    }
+}
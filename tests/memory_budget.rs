@@ -0,0 +1,35 @@
+use pointpca2_rs::knn_search::Neighborhood;
+use pointpca2_rs::{compute_pointpca2_with_config, PointPca2Config};
+
+mod common;
+
+use common::voxelized_cloud;
+
+fn pooled_bits(neighborhood: Neighborhood, memory_budget: Option<usize>) -> Vec<u64> {
+    let (points_a, colors_a) = voxelized_cloud(0.);
+    let (points_b, colors_b) = voxelized_cloud(0.4);
+    let config = PointPca2Config {
+        neighborhood,
+        memory_budget,
+        ..Default::default()
+    };
+    let result =
+        compute_pointpca2_with_config(points_a, colors_a, points_b, colors_b, &config).unwrap();
+    result.predictors.iter().map(|x| x.to_bits()).collect()
+}
+
+#[test]
+fn chunked_pooling_matches_the_whole_cloud() {
+    for neighborhood in [Neighborhood::Knn(27), Neighborhood::Radius(2.5)] {
+        let expected = pooled_bits(neighborhood, None);
+        // Chunks of a few dozen points, then a single chunk
+        for memory_budget in [20_000, 1_000_000] {
+            let chunked = pooled_bits(neighborhood, Some(memory_budget));
+            assert_eq!(
+                chunked, expected,
+                "{:?}, {} bytes",
+                neighborhood, memory_budget
+            );
+        }
+    }
+}
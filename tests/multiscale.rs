@@ -0,0 +1,57 @@
+use pointpca2_rs::{compute_pointpca2_multiscale, compute_pointpca2_with_config};
+use pointpca2_rs::{PointPca2Config, PointPca2Error};
+
+mod common;
+
+use common::voxelized_cloud;
+
+fn single_scale(scale: usize, config: &PointPca2Config) -> Vec<u64> {
+    let (points_a, colors_a) = voxelized_cloud(0.);
+    let (points_b, colors_b) = voxelized_cloud(0.4);
+    let config = PointPca2Config {
+        neighborhood: pointpca2_rs::knn_search::Neighborhood::Knn(scale),
+        ..config.clone()
+    };
+    let result =
+        compute_pointpca2_with_config(points_a, colors_a, points_b, colors_b, &config).unwrap();
+    result.predictors.iter().map(|x| x.to_bits()).collect()
+}
+
+#[test]
+fn scales_match_single_scale_runs() {
+    let scales = [9, 27, 81];
+    for memory_budget in [None, Some(50_000)] {
+        let config = PointPca2Config {
+            memory_budget,
+            ..PointPca2Config::default()
+        };
+        let (points_a, colors_a) = voxelized_cloud(0.);
+        let (points_b, colors_b) = voxelized_cloud(0.4);
+        let result =
+            compute_pointpca2_multiscale(points_a, colors_a, points_b, colors_b, &scales, &config)
+                .unwrap();
+        let dimension = config.predictors.dimension();
+        assert_eq!(result.predictors.len(), scales.len() * dimension);
+        assert_eq!(result.labels.len(), result.predictors.len());
+        assert_eq!(result.labels[dimension], "k27_color_mean_y");
+        for (s, &scale) in scales.iter().enumerate() {
+            let predictors = result.predictors.columns(s * dimension, dimension);
+            let bits = predictors.iter().map(|x| x.to_bits()).collect::<Vec<_>>();
+            assert_eq!(single_scale(scale, &config), bits, "scale {}", scale);
+        }
+    }
+}
+
+#[test]
+fn empty_scales_are_rejected() {
+    let (points, colors) = voxelized_cloud(0.);
+    let result = compute_pointpca2_multiscale(
+        points.clone(),
+        colors.clone(),
+        points,
+        colors,
+        &[],
+        &PointPca2Config::default(),
+    );
+    assert!(matches!(result, Err(PointPca2Error::NoScales)));
+}
@@ -0,0 +1,145 @@
+use pointpca2_rs::knn_search::{self, KnnGraph, NO_NEIGHBOR};
+use pointpca2_rs::neighbor_index::{self, IndexBackend};
+
+mod common;
+
+use common::{random_points, voxelized_cloud};
+
+const BACKENDS: [IndexBackend; 6] = [
+    IndexBackend::KdTree,
+    IndexBackend::VoxelGrid { cell_size: None },
+    // Cells much smaller and much larger than the point spacing
+    IndexBackend::VoxelGrid {
+        cell_size: Some(0.3),
+    },
+    IndexBackend::VoxelGrid {
+        cell_size: Some(8.),
+    },
+    // Without a check budget, the approximate search is exact
+    IndexBackend::ApproximateKdTree {
+        max_checks: usize::MAX,
+    },
+    IndexBackend::ApproximateKdTree { max_checks: 256 },
+];
+
+#[test]
+fn backends_match_brute_force() {
+    let clouds = [random_points(1, 2000, 20.), voxelized_cloud(0.).0];
+    for points in clouds {
+        let reference = neighbor_index::build_index(&points, &IndexBackend::BruteForce);
+        // Points of the cloud, points around it and points far from every occupied cell
+        let mut queries = points.iter().step_by(37).copied().collect::<Vec<_>>();
+        queries.extend(random_points(2, 50, 30.));
+        queries.extend([[500., 3., 2.], [-200., -200., 40.], [10., 1e4, 10.]]);
+        for backend in BACKENDS {
+            let index = neighbor_index::build_index(&points, &backend);
+            for query in &queries {
+                // With a check budget, the approximate search is only exact for radius queries
+                let exact_knn = backend != IndexBackend::ApproximateKdTree { max_checks: 256 };
+                for k in [1, 10, 50].into_iter().filter(|_| exact_knn) {
+                    assert_eq!(
+                        index.knn(query, k),
+                        reference.knn(query, k),
+                        "{:?}, knn {} of {:?}",
+                        backend,
+                        k,
+                        query
+                    );
+                }
+                for radius in [0.7, 2.5] {
+                    assert_eq!(
+                        index.radius(query, radius),
+                        reference.radius(query, radius),
+                        "{:?}, radius {} of {:?}",
+                        backend,
+                        radius,
+                        query
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn in_place_queries_match_knn() {
+    let points = voxelized_cloud(0.).0;
+    let queries = random_points(3, 100, 30.);
+    for backend in BACKENDS {
+        let index = neighbor_index::build_index(&points, &backend);
+        for query in &queries {
+            // Longer than the cloud, so that the last slots stay empty
+            for k in [20, points.len() + 5] {
+                let expected = index.knn(query, k);
+                let mut indices = vec![0; k];
+                let mut distances = vec![0.; k];
+                index.knn_into(query, &mut indices, Some(&mut distances));
+                let mut indices_only = vec![0; k];
+                index.knn_into(query, &mut indices_only, None);
+                assert_eq!(indices, indices_only, "{:?}", backend);
+                let found = k.min(points.len());
+                assert!(indices[found..].iter().all(|&idx| idx == NO_NEIGHBOR));
+                assert!(distances[found..].iter().all(|&dist| dist == f64::INFINITY));
+                let row = indices.into_iter().zip(distances).take(found);
+                assert_eq!(row.collect::<Vec<_>>(), expected, "{:?}", backend);
+            }
+        }
+    }
+}
+
+#[test]
+fn knn_graph_rows_match_nearest_n() {
+    let points = random_points(4, 3000, 20.);
+    let queries = random_points(5, 500, 20.);
+    let index = neighbor_index::build_index(&points, &IndexBackend::KdTree);
+    for with_distances in [false, true] {
+        let mut graph = KnnGraph::new(queries.len(), 16, with_distances);
+        // Refilling with fewer queries reuses the buffers of the first fill
+        for queries in [&queries[..], &queries[..200]] {
+            graph.fill(index.as_ref(), queries);
+            assert_eq!(graph.len(), queries.len());
+            for (i, query) in queries.iter().enumerate() {
+                let expected = knn_search::nearest_n_with_distances(index.as_ref(), query, 16);
+                let neighbors = expected.iter().map(|&(idx, _)| idx).collect::<Vec<_>>();
+                assert_eq!(graph.neighbors(i), neighbors);
+                assert_eq!(
+                    graph.neighbors(i),
+                    knn_search::nearest_n(index.as_ref(), query, 16)
+                );
+                if let Some(distances) = graph.neighbor_distances(i) {
+                    let expected = expected.iter().map(|&(_, dist)| dist * dist);
+                    for (actual, expected) in distances.iter().zip(expected) {
+                        assert!((actual - expected).abs() <= 1e-12 * expected.max(1.));
+                    }
+                }
+            }
+        }
+        assert_eq!(graph.squared_distances().is_some(), with_distances);
+    }
+}
+
+#[test]
+fn recall_grows_with_the_check_budget() {
+    let points = random_points(6, 5000, 20.);
+    let queries = random_points(7, 1000, 20.);
+    let recall = |backend| knn_search::measure_recall(&points, &queries, &backend, 16, 300, 1);
+    let exact = [
+        IndexBackend::KdTree,
+        IndexBackend::BruteForce,
+        IndexBackend::VoxelGrid { cell_size: None },
+        IndexBackend::ApproximateKdTree {
+            max_checks: usize::MAX,
+        },
+    ];
+    for backend in exact {
+        let report = recall(backend);
+        assert_eq!(report.sample_size, 300);
+        assert_eq!(report.recall, 1., "{:?}", backend);
+        assert_eq!(report.mean_kth_distance_ratio, 1., "{:?}", backend);
+    }
+    let recalls = [8, 32, 128, 512]
+        .map(|max_checks| recall(IndexBackend::ApproximateKdTree { max_checks }).recall);
+    assert!(recalls[0] < 1., "{:?}", recalls);
+    assert!(recalls.windows(2).all(|w| w[0] <= w[1]), "{:?}", recalls);
+    assert!(recalls[0] < recalls[3], "{:?}", recalls);
+}
@@ -0,0 +1,143 @@
+use pointpca2_rs::features;
+use pointpca2_rs::knn_search::{self, Neighborhood};
+use pointpca2_rs::neighbor_index::{self, IndexBackend};
+use pointpca2_rs::PointPca2Config;
+use std::collections::HashSet;
+
+mod common;
+
+use common::voxelized_cloud;
+
+const BACKENDS: [IndexBackend; 4] = [
+    IndexBackend::KdTree,
+    IndexBackend::VoxelGrid { cell_size: None },
+    IndexBackend::BruteForce,
+    IndexBackend::ApproximateKdTree { max_checks: 256 },
+];
+
+// Flat 5 x 5 grid of unit spacing, point 12 being the center
+fn flat_grid() -> Vec<[f64; 3]> {
+    let mut points = Vec::new();
+    for x in 0..5 {
+        for y in 0..5 {
+            points.push([x as f64, y as f64, 0.]);
+        }
+    }
+    points
+}
+
+#[test]
+fn radius_neighbors_are_strictly_closer_than_the_radius() {
+    let points = flat_grid();
+    let center = points[12];
+    for backend in BACKENDS {
+        let index = neighbor_index::build_index(&points, &backend);
+        // The four direct neighbors lie exactly at the radius
+        let neighbors = knn_search::within_radius(index.as_ref(), &center, 1.);
+        assert_eq!(neighbors, [12], "{:?}", backend);
+        let neighbors = knn_search::within_radius(index.as_ref(), &center, 1. + 1e-9);
+        assert_eq!(neighbors, [12, 7, 11, 13, 17], "{:?}", backend);
+        // Without any point within the radius, the nearest one is kept
+        let radius = Neighborhood::Radius(1.);
+        let far = [10., 2., 0.];
+        let neighbors = knn_search::neighborhood(index.as_ref(), &far, &radius);
+        assert_eq!(neighbors, [22], "{:?}", backend);
+    }
+}
+
+#[test]
+fn hybrid_neighborhoods_keep_the_minimum_count() {
+    let points = flat_grid();
+    let center = points[12];
+    let index = neighbor_index::build_index(&points, &IndexBackend::KdTree);
+    let hybrid = |k, radius, min_count| {
+        let neighborhood = Neighborhood::Hybrid {
+            k,
+            radius,
+            min_count,
+        };
+        knn_search::neighborhood(index.as_ref(), &center, &neighborhood)
+    };
+    // Only the center is within the radius, the nearest neighbors complete the minimum
+    assert_eq!(hybrid(10, 0.5, 4), [12, 7, 11, 13]);
+    // The 3 x 3 block is within the radius, at most k of it is kept
+    assert_eq!(hybrid(10, 1.5, 4).len(), 9);
+    assert_eq!(hybrid(6, 1.5, 4).len(), 6);
+    assert_eq!(hybrid(6, 0.5, 0), [12]);
+}
+
+#[test]
+fn variable_size_neighborhoods_produce_their_own_statistics() {
+    let (points, colors) = voxelized_cloud(0.);
+    let index = neighbor_index::build_index(&points, &IndexBackend::BruteForce);
+    let neighborhoods = [
+        Neighborhood::Radius(1.5),
+        Neighborhood::Hybrid {
+            k: 12,
+            radius: 2.,
+            min_count: 6,
+        },
+    ];
+    for neighborhood in neighborhoods {
+        let config = PointPca2Config {
+            neighborhood,
+            ..PointPca2Config::default()
+        };
+        let (features, _) = features::compute_features_with_config(
+            points.clone(),
+            colors.clone(),
+            points.clone(),
+            colors.clone(),
+            &config,
+        );
+        assert_eq!(features.nrows(), points.len());
+        let mut sizes = HashSet::new();
+        for (i, point) in points.iter().enumerate() {
+            let neighbors = knn_search::neighborhood(index.as_ref(), point, &neighborhood);
+            sizes.insert(neighbors.len());
+            let n = neighbors.len() as f64;
+            for j in 0..3 {
+                let mean = neighbors.iter().map(|&p| colors[p][j] as f64).sum::<f64>() / n;
+                let variance = neighbors
+                    .iter()
+                    .map(|&p| (colors[p][j] as f64 - mean).powi(2))
+                    .sum::<f64>()
+                    / n;
+                // Colors mean and variance of the reference and distorted neighborhoods
+                for (column, expected) in [(6, mean), (12, mean), (18, variance), (24, variance)] {
+                    let actual = features[(i, column + j)];
+                    assert!(
+                        (actual - expected).abs() <= 1e-9 * expected.abs().max(1.),
+                        "{:?}: point {}, column {}: {} != {}",
+                        neighborhood,
+                        i,
+                        column + j,
+                        actual,
+                        expected
+                    );
+                }
+            }
+            assert!(features.row(i).iter().all(|x| x.is_finite()));
+        }
+        assert!(sizes.len() > 1, "{:?}", neighborhood);
+    }
+}
+
+#[test]
+fn search_size_features_match_a_knn_neighborhood() {
+    let (points_a, colors_a) = voxelized_cloud(0.);
+    let (points_b, colors_b) = voxelized_cloud(0.4);
+    let config = PointPca2Config {
+        neighborhood: Neighborhood::Knn(27),
+        ..Default::default()
+    };
+    let (expected, _) = features::compute_features_with_config(
+        points_a.clone(),
+        colors_a.clone(),
+        points_b.clone(),
+        colors_b.clone(),
+        &config,
+    );
+    let features = features::compute_features(points_a, colors_a, points_b, colors_b, 27);
+    assert_eq!(features, expected);
+}
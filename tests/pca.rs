@@ -0,0 +1,147 @@
+use na::DMatrix;
+use na::Vector3;
+use nalgebra as na;
+use pointpca2_rs::pca::{
+    CovarianceEstimator, CovarianceOptions, CovarianceWeighting, NeighborhoodClass,
+};
+use pointpca2_rs::{pca, utils::SplitMix64};
+
+fn random_neighborhood(rng: &mut SplitMix64, nrows: usize, scales: [f64; 3]) -> DMatrix<f64> {
+    let mut uniform = || (rng.next_u64() >> 11) as f64 / (1u64 << 53) as f64 - 0.5;
+    // Random rotation of an anisotropic cloud, so the principal axes are not axis aligned
+    let rotation = na::Rotation3::from_euler_angles(uniform() * 6., uniform() * 6., uniform() * 6.);
+    let mut neighborhood = DMatrix::zeros(nrows, 3);
+    for i in 0..nrows {
+        let local = na::Vector3::new(
+            uniform() * scales[0],
+            uniform() * scales[1],
+            uniform() * scales[2],
+        );
+        let point = rotation * local + na::Vector3::new(100., -50., 20.);
+        neighborhood.row_mut(i).copy_from(&point.transpose());
+    }
+    neighborhood
+}
+
+#[test]
+fn fixed_size_pca_matches_dynamic_pca() {
+    let mut rng = SplitMix64::new(7);
+    let shapes = [[10., 5., 1.], [3., 2., 1.], [10., 1., 0.], [1., 1., 0.5]];
+    for shape in shapes {
+        for nrows in [4, 27, 81] {
+            let neighborhood = random_neighborhood(&mut rng, nrows, shape);
+            let expected = pca::compute_pca_dynamic(&neighborhood);
+            let result = pca::compute_pca(&neighborhood);
+            let max_difference = (&expected - &result).abs().max();
+            assert!(
+                max_difference < 1e-9,
+                "PCA results differ by {} for {} rows with scales {:?}.",
+                max_difference,
+                nrows,
+                shape
+            );
+        }
+    }
+}
+
+const ESTIMATES: [CovarianceOptions; 4] = [
+    CovarianceOptions {
+        weighting: CovarianceWeighting::Uniform,
+        estimator: CovarianceEstimator::Standard,
+        unbiased: true,
+    },
+    CovarianceOptions {
+        weighting: CovarianceWeighting::Gaussian { sigma: Some(0.5) },
+        estimator: CovarianceEstimator::Standard,
+        unbiased: true,
+    },
+    CovarianceOptions {
+        weighting: CovarianceWeighting::Uniform,
+        estimator: CovarianceEstimator::Irls { iterations: 10 },
+        unbiased: true,
+    },
+    CovarianceOptions {
+        weighting: CovarianceWeighting::Uniform,
+        estimator: CovarianceEstimator::Trimmed { fraction: 0.2 },
+        unbiased: true,
+    },
+];
+
+#[test]
+fn unbiased_estimates_of_single_points_are_zero() {
+    let rows = [Vector3::<f64>::new(1., 2., 3.)];
+    for options in ESTIMATES {
+        let (eigenvalues, eigenvectors) = pca::compute_pca3_with_options(&rows, &rows[0], &options);
+        assert_eq!(eigenvalues, Vector3::zeros(), "{:?}", options);
+        assert!(eigenvectors.iter().all(|x| x.is_finite()), "{:?}", options);
+    }
+}
+
+// Neighbors on the plane z = 0 around the query point, and noutliers neighbors at height z
+fn plane_with_outliers(noutliers: usize, z: f64) -> Vec<Vector3<f64>> {
+    let mut rng = SplitMix64::new(11);
+    let mut uniform = || (rng.next_u64() >> 11) as f64 / (1u64 << 53) as f64 * 2. - 1.;
+    let mut rows = (0..54)
+        .map(|_| Vector3::new(uniform(), uniform(), 0.))
+        .collect::<Vec<_>>();
+    rows.extend((0..noutliers).map(|_| Vector3::new(uniform(), uniform(), z)));
+    rows
+}
+
+// |cos| of the angle between the estimated normal and the plane normal
+fn normal_alignment(rows: &[Vector3<f64>], options: &CovarianceOptions) -> f64 {
+    let (_, eigenvectors) = pca::compute_pca3_with_options(rows, &Vector3::zeros(), options);
+    eigenvectors[(2, 2)].abs()
+}
+
+#[test]
+fn robust_estimates_recover_the_plane_normal_despite_outliers() {
+    // A tenth of the neighbors off the plane
+    let rows = plane_with_outliers(6, 2.);
+    assert!(normal_alignment(&rows, &CovarianceOptions::default()) < 0.5);
+    for options in [&ESTIMATES[1], &ESTIMATES[3]] {
+        let alignment = normal_alignment(&rows, options);
+        assert!(alignment > 0.999, "{:?}: {}", options, alignment);
+    }
+    // Huber weights only bound the influence of the outliers, which also shape the first
+    // estimate, so IRLS is checked with fewer of them
+    let rows = plane_with_outliers(2, 2.);
+    let standard = normal_alignment(&rows, &CovarianceOptions::default());
+    let irls = normal_alignment(&rows, &ESTIMATES[2]);
+    assert!(standard < 0.95, "{}", standard);
+    assert!(irls > 0.995, "{}", irls);
+}
+
+fn class_of(rows: &[Vector3<f64>]) -> NeighborhoodClass {
+    let options = CovarianceOptions::default();
+    let (eigenvalues, _) = pca::compute_pca3_with_options(rows, &rows[0], &options);
+    pca::classify_neighborhood(&eigenvalues, rows)
+}
+
+#[test]
+fn neighborhoods_are_classified_by_rank() {
+    let mut rng = SplitMix64::new(5);
+    let mut uniform = || (rng.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+    let random = (0..27)
+        .map(|_| Vector3::new(uniform(), uniform(), uniform()))
+        .collect::<Vec<_>>();
+    // Far from the origin, so that centering leaves round-off in the spread
+    let origin = Vector3::new(1e6, -1e6, 1e6);
+    let coplanar = random.iter().map(|p| Vector3::new(p.x, p.y, 0.) + origin);
+    let collinear = random.iter().map(|p| Vector3::new(p.x, 0., 0.) + origin);
+    let duplicates = random.iter().map(|_| origin);
+    assert_eq!(class_of(&random), NeighborhoodClass::FullRank);
+    assert_eq!(
+        class_of(&coplanar.collect::<Vec<_>>()),
+        NeighborhoodClass::Planar
+    );
+    assert_eq!(
+        class_of(&collinear.collect::<Vec<_>>()),
+        NeighborhoodClass::Linear
+    );
+    assert_eq!(
+        class_of(&duplicates.collect::<Vec<_>>()),
+        NeighborhoodClass::Degenerate
+    );
+    assert_eq!(class_of(&[origin]), NeighborhoodClass::Degenerate);
+}
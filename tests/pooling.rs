@@ -0,0 +1,86 @@
+use na::DMatrix;
+use nalgebra as na;
+use pointpca2_rs::pooling::{self, Accumulator, Pool, QuantileAccumulator};
+use pointpca2_rs::utils::SplitMix64;
+
+// Skewed samples (squared exponential variates), so quantiles are not evenly spaced
+fn random_rows(seed: u64, nrows: usize, ncols: usize) -> DMatrix<f64> {
+    let mut rng = SplitMix64::new(seed);
+    DMatrix::from_fn(nrows, ncols, |_, j| {
+        let u = (rng.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+        (j + 1) as f64 * (-u.ln()).powi(2)
+    })
+}
+
+#[test]
+fn accumulators_match_materialized_pooling() {
+    let rows = random_rows(1, 20000, 4);
+    for technique in [
+        "mean_pooling",
+        "max_pooling",
+        "min_pooling",
+        "variance_pooling",
+    ] {
+        let pool = Pool::new(technique).unwrap();
+        let expected = pool.pool(&rows);
+        let mut sequential = pool.accumulator(rows.ncols());
+        sequential.push_rows(&rows);
+        let parallel = pooling::accumulate_parallel(&rows, || pool.accumulator(rows.ncols()));
+        let mut by_row = pool.accumulator(rows.ncols());
+        for i in 0..rows.nrows() {
+            by_row.push(rows.row(i).transpose().as_slice());
+        }
+        // Pushing the rows sequentially keeps the summation order of mean pooling
+        if technique == "mean_pooling" {
+            assert_eq!(expected, sequential.finish());
+        }
+        for result in [sequential.finish(), parallel.finish(), by_row.finish()] {
+            for (a, b) in expected.iter().zip(result.iter()) {
+                assert!(
+                    (a - b).abs() <= 1e-9 * a.abs(),
+                    "{}: {} != {}",
+                    technique,
+                    a,
+                    b
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn quantile_rank_error_is_bounded() {
+    let nrows = 200000;
+    let rows = random_rows(2, nrows, 2);
+    let quantiles = [0.001, 0.01, 0.1, 0.25, 0.5, 0.75, 0.9, 0.99, 0.999];
+    let parallel =
+        pooling::accumulate_parallel(&rows, || QuantileAccumulator::new(2, &quantiles)).finish();
+    let mut sequential = QuantileAccumulator::new(2, &quantiles);
+    sequential.push_rows(&rows);
+    let sequential = sequential.finish();
+    for j in 0..rows.ncols() {
+        let mut sorted = rows.column(j).iter().copied().collect::<Vec<_>>();
+        sorted.sort_by(f64::total_cmp);
+        for (i, q) in quantiles.iter().enumerate() {
+            for estimates in [&parallel, &sequential] {
+                let estimate = estimates[i * rows.ncols() + j];
+                let rank = sorted.partition_point(|&x| x < estimate) as f64 / nrows as f64;
+                assert!((rank - q).abs() < 3e-3, "q = {}: rank {}", q, rank);
+            }
+        }
+    }
+}
+
+#[test]
+fn approximate_median_is_close_to_exact_median() {
+    let rows = random_rows(3, 50001, 3);
+    let pool = Pool::new("median_pooling").unwrap();
+    let exact = pool.pool(&rows);
+    let approximate = pooling::accumulate_parallel(&rows, || pool.accumulator(3)).finish();
+    for j in 0..3 {
+        let mut sorted = rows.column(j).iter().copied().collect::<Vec<_>>();
+        sorted.sort_by(f64::total_cmp);
+        let rank = |x: f64| sorted.partition_point(|&y| y < x) as f64 / sorted.len() as f64;
+        assert!((rank(exact[j]) - rank(approximate[j])).abs() < 3e-3);
+    }
+}
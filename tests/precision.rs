@@ -0,0 +1,125 @@
+use pointpca2_rs::features;
+use pointpca2_rs::knn_search::Neighborhood;
+use pointpca2_rs::precision::Precision;
+use pointpca2_rs::utils::SplitMix64;
+use pointpca2_rs::{compute_pointpca2_with_config, PointPca2Config};
+
+mod common;
+
+use common::{Cloud, Surface};
+
+// Deviation from the f64 predictors allowed in f32, relative to max(|f64 value|, 1)
+const F32_TOLERANCE: f64 = 1e-3;
+
+fn surface_cloud(offset: f64, origin: f64, voxelized: bool) -> Cloud {
+    Surface {
+        origin,
+        voxelized,
+        ..Surface::new(offset)
+    }
+    .cloud()
+}
+
+#[test]
+fn f32_predictors_stay_close_to_f64() {
+    // Voxelized content, smooth content and coordinates far from the origin
+    for (origin, voxelized) in [(0., true), (0., false), (1e5, true)] {
+        for memory_budget in [None, Some(50_000)] {
+            let run = |precision| {
+                let (points_a, colors_a) = surface_cloud(0., origin, voxelized);
+                let (points_b, colors_b) = surface_cloud(0.4, origin, voxelized);
+                let config = PointPca2Config {
+                    precision,
+                    memory_budget,
+                    ..PointPca2Config::new(27)
+                };
+                compute_pointpca2_with_config(points_a, colors_a, points_b, colors_b, &config)
+                    .unwrap()
+            };
+            let reference = run(Precision::F64);
+            let reduced = run(Precision::F32);
+            assert_eq!(reference.predictor_names, reduced.predictor_names);
+            for (j, name) in reference.predictor_names.iter().enumerate() {
+                let (expected, actual) = (reference.predictors[j], reduced.predictors[j]);
+                assert!(
+                    (expected - actual).abs() <= F32_TOLERANCE * expected.abs().max(1.),
+                    "{}: {} in f64, {} in f32 (origin {})",
+                    name,
+                    expected,
+                    actual,
+                    origin
+                );
+            }
+        }
+    }
+}
+
+// Surface of 3025 points, with colors following the grid or drawn at random
+fn corpus_cloud(offset: f64, origin: f64, voxelized: bool, random_colors: bool) -> Cloud {
+    let (points, mut colors) = Surface {
+        size: 55,
+        origin,
+        voxelized,
+        ..Surface::new(offset)
+    }
+    .cloud();
+    if random_colors {
+        let mut rng = SplitMix64::new(offset.to_bits());
+        for color in colors.iter_mut() {
+            *color = [0; 3].map(|_| rng.next_below(256) as u8);
+        }
+    }
+    (points, colors)
+}
+
+// Corpus of the deviation documented in the README, whose largest value is 4.68e-4 for
+// parallelity_e1 on the voxelized surface at k = 27
+#[test]
+fn f32_deviation_over_the_corpus() {
+    let mut deviation = 0f64;
+    for origin in [0., 1e5, 1e6] {
+        for k in [27, 81] {
+            for voxelized in [true, false] {
+                for random_colors in [false, true] {
+                    let run = |precision| {
+                        let cloud_a = corpus_cloud(0., origin, voxelized, random_colors);
+                        let cloud_b = corpus_cloud(0.4, origin, voxelized, random_colors);
+                        let config = PointPca2Config {
+                            precision,
+                            ..PointPca2Config::new(k)
+                        };
+                        compute_pointpca2_with_config(
+                            cloud_a.0, cloud_a.1, cloud_b.0, cloud_b.1, &config,
+                        )
+                        .unwrap()
+                    };
+                    let reference = run(Precision::F64);
+                    let reduced = run(Precision::F32);
+                    for (j, name) in reference.predictor_names.iter().enumerate() {
+                        let (expected, actual) = (reference.predictors[j], reduced.predictors[j]);
+                        assert!(
+                            (expected - actual).abs() <= F32_TOLERANCE * expected.abs().max(1.),
+                            "{}: {} in f64, {} in f32 (origin {}, k {})",
+                            name,
+                            expected,
+                            actual,
+                            origin,
+                            k
+                        );
+                        deviation = deviation.max((expected - actual).abs());
+                    }
+                }
+            }
+        }
+    }
+    assert!(deviation <= 4.7e-4, "{}", deviation);
+}
+
+#[test]
+fn f32_chunks_hold_more_points() {
+    let neighborhood = Neighborhood::Knn(27);
+    let budget = 1 << 20;
+    let f64_chunk = features::chunk_size_for_budget(budget, &neighborhood, 40, Precision::F64);
+    let f32_chunk = features::chunk_size_for_budget(budget, &neighborhood, 40, Precision::F32);
+    assert!(f32_chunk > f64_chunk);
+}
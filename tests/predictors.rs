@@ -0,0 +1,175 @@
+use na::DMatrix;
+use nalgebra as na;
+use pointpca2_rs::predictors::{LocalFeatures, Predictor, PredictorRegistry, PREDICTORS_DIMENSION};
+use pointpca2_rs::{compute_pointpca2_with_config, PointPca2Config, PointPca2Error};
+
+// Distance between the mean colors of the two neighborhoods
+#[derive(Debug)]
+struct ColorDistance;
+
+impl Predictor for ColorDistance {
+    fn name(&self) -> &str {
+        "color_distance"
+    }
+
+    fn width(&self) -> usize {
+        1
+    }
+
+    fn compute(&self, local_features: &LocalFeatures) -> DMatrix<f64> {
+        let difference = local_features.colors_mean_a() - local_features.colors_mean_b();
+        DMatrix::from_fn(local_features.nrows(), 1, |i, _| difference.row(i).norm())
+    }
+}
+
+fn grid_cloud(offset: f64) -> (Vec<[f64; 3]>, Vec<[u8; 3]>) {
+    let mut points = Vec::new();
+    let mut colors = Vec::new();
+    for x in 0..30 {
+        for y in 0..30 {
+            points.push([x as f64, y as f64, ((x * y) as f64 / 60. + offset).round()]);
+            colors.push([(x * 8) as u8, (y * 8) as u8, ((x + y) * 4) as u8]);
+        }
+    }
+    (points, colors)
+}
+
+#[test]
+fn registered_predictors_extend_the_output() {
+    let builtin = PredictorRegistry::builtin();
+    assert_eq!(builtin.dimension(), PREDICTORS_DIMENSION);
+    assert_eq!(builtin.column_names().len(), PREDICTORS_DIMENSION);
+    let mut registry = PredictorRegistry::builtin();
+    registry.register(ColorDistance).unwrap();
+    let run = |predictors: PredictorRegistry| {
+        let (points_a, colors_a) = grid_cloud(0.);
+        let (points_b, colors_b) = grid_cloud(0.5);
+        let config = PointPca2Config {
+            predictors,
+            ..PointPca2Config::new(27)
+        };
+        compute_pointpca2_with_config(points_a, colors_a, points_b, colors_b, &config).unwrap()
+    };
+    let reference = run(builtin);
+    let extended = run(registry);
+    assert_eq!(extended.predictors.len(), PREDICTORS_DIMENSION + 1);
+    assert_eq!(
+        extended.predictor_names.last().map(String::as_str),
+        Some("color_distance")
+    );
+    assert_eq!(
+        reference.predictors.as_slice(),
+        &extended.predictors.as_slice()[..PREDICTORS_DIMENSION]
+    );
+    assert!(extended.predictors[PREDICTORS_DIMENSION] > 0.);
+}
+
+#[test]
+fn duplicate_names_are_rejected() {
+    let mut registry = PredictorRegistry::new();
+    registry.register(ColorDistance).unwrap();
+    let duplicate = Some(PointPca2Error::DuplicatePredictor {
+        name: "color_distance".to_string(),
+    });
+    assert_eq!(registry.register(ColorDistance).err(), duplicate);
+    registry.register_normal_predictors().unwrap();
+    let duplicate = Some(PointPca2Error::DuplicatePredictor {
+        name: "point_to_plane_reference".to_string(),
+    });
+    assert_eq!(registry.register_normal_predictors().err(), duplicate);
+    assert_eq!(registry.dimension(), 5);
+}
+
+// Declares one more column than it returns
+#[derive(Debug)]
+struct WrongWidth;
+
+impl Predictor for WrongWidth {
+    fn name(&self) -> &str {
+        "wrong_width"
+    }
+
+    fn width(&self) -> usize {
+        2
+    }
+
+    fn compute(&self, local_features: &LocalFeatures) -> DMatrix<f64> {
+        DMatrix::zeros(local_features.nrows(), 1)
+    }
+}
+
+#[test]
+fn predictors_of_the_wrong_width_are_rejected() {
+    let mut predictors = PredictorRegistry::builtin();
+    predictors.register(WrongWidth).unwrap();
+    let expected = Some(PointPca2Error::PredictorWidthMismatch {
+        name: "wrong_width".to_string(),
+        width: 2,
+        columns: 1,
+    });
+    for memory_budget in [None, Some(50_000)] {
+        let config = PointPca2Config {
+            predictors: predictors.clone(),
+            memory_budget,
+            ..PointPca2Config::new(27)
+        };
+        let (points_a, colors_a) = grid_cloud(0.);
+        let (points_b, colors_b) = grid_cloud(0.5);
+        let result = compute_pointpca2_with_config(points_a, colors_a, points_b, colors_b, &config);
+        assert_eq!(result.err(), expected);
+    }
+    let local_features = DMatrix::zeros(3, 42);
+    assert_eq!(
+        predictors.compute_predictors(local_features).err(),
+        expected
+    );
+}
+
+#[test]
+fn normal_predictors_vanish_on_identical_clouds() {
+    let mut registry = PredictorRegistry::new();
+    registry.register_normal_predictors().unwrap();
+    let run = |offset: f64| {
+        let (points_a, colors_a) = grid_cloud(0.);
+        let (points_b, colors_b) = grid_cloud(offset);
+        let config = PointPca2Config {
+            predictors: registry.clone(),
+            ..PointPca2Config::new(27)
+        };
+        compute_pointpca2_with_config(points_a, colors_a, points_b, colors_b, &config).unwrap()
+    };
+    let identical = run(0.);
+    assert_eq!(
+        identical.predictor_names,
+        [
+            "point_to_plane_reference",
+            "point_to_plane_distorted",
+            "point_to_plane_symmetric",
+            "normal_similarity"
+        ]
+    );
+    for j in 0..3 {
+        assert!(identical.predictors[j].abs() < 1e-9);
+    }
+    assert!((identical.predictors[3] - 1.).abs() < 1e-6);
+    let distorted = run(0.5);
+    for j in 0..3 {
+        assert!(distorted.predictors[j] > 1e-3);
+    }
+    assert!(distorted.predictors[3] < identical.predictors[3]);
+}
+
+#[test]
+fn point_to_plane_reference_measures_the_distorted_neighborhood() {
+    let mut registry = PredictorRegistry::new();
+    registry.register_normal_predictors().unwrap();
+    // Nearest points on the same plane, distorted neighbors spread 2 away from it
+    let mut local_features = DMatrix::zeros(1, 42);
+    local_features[(0, 23)] = 4.;
+    local_features[(0, 41)] = 1.;
+    let predictors = registry.compute_point_predictors(&local_features).unwrap();
+    assert_eq!(
+        predictors.columns(0, 3),
+        DMatrix::from_row_slice(1, 3, &[2., 0., 2.])
+    );
+}
@@ -0,0 +1,174 @@
+use ordered_float::OrderedFloat;
+use pointpca2_rs::preprocessing::{self, OutlierFilter};
+use pointpca2_rs::utils::SplitMix64;
+use pointpca2_rs::{compute_pointpca2_with_config, PointPca2Config};
+use std::collections::BTreeMap;
+
+mod common;
+
+use common::{voxelized_cloud, Cloud};
+
+// Isolated points far above the surface, each far from the others
+const FLYING_POINTS: [[f64; 3]; 4] = [
+    [3., 5., 40.],
+    [12., 20., 60.],
+    [20., 2., -30.],
+    [-15., 10., 5.],
+];
+
+// The surface with the flying points inserted between its points
+fn cloud_with_flying_points() -> Cloud {
+    let (mut points, mut colors) = voxelized_cloud(0.);
+    for (i, &point) in FLYING_POINTS.iter().enumerate() {
+        points.insert(100 * i + 50, point);
+        colors.insert(100 * i + 50, [255, 0, 0]);
+    }
+    (points, colors)
+}
+
+#[test]
+fn filters_remove_exactly_the_flying_points() {
+    let filters = [
+        OutlierFilter::Statistical {
+            k: 8,
+            std_ratio: 2.,
+        },
+        OutlierFilter::Radius {
+            radius: 1.5,
+            min_neighbors: 2,
+        },
+    ];
+    let (surface_points, surface_colors) = voxelized_cloud(0.);
+    for filter in filters {
+        let (points, colors) = cloud_with_flying_points();
+        let (points, colors, removed) = preprocessing::remove_outliers(points, colors, &filter);
+        assert_eq!(removed, FLYING_POINTS.len(), "{:?}", filter);
+        assert_eq!(points, surface_points, "{:?}", filter);
+        assert_eq!(colors, surface_colors, "{:?}", filter);
+    }
+}
+
+#[test]
+fn removed_counts_are_reported() {
+    let (points_a, colors_a) = cloud_with_flying_points();
+    let (points_b, colors_b) = voxelized_cloud(0.4);
+    let config = PointPca2Config {
+        outlier_filter_a: Some(OutlierFilter::Statistical {
+            k: 8,
+            std_ratio: 2.,
+        }),
+        outlier_filter_b: Some(OutlierFilter::Radius {
+            radius: 1.5,
+            min_neighbors: 2,
+        }),
+        ..PointPca2Config::new(27)
+    };
+    let result =
+        compute_pointpca2_with_config(points_a, colors_a, points_b, colors_b, &config).unwrap();
+    assert_eq!(result.outliers_removed_a, FLYING_POINTS.len());
+    assert_eq!(result.outliers_removed_b, 0);
+}
+
+fn rgb_to_yuv(rgb: [u8; 3]) -> [u8; 3] {
+    let [r, g, b] = rgb.map(|c| c as f64);
+    let y = 0.2126 * r + 0.2126 * g + 0.0722 * b;
+    let u = -0.1146 * r + -0.3854 * g + 0.5000 * b + 128.;
+    let v = 0.5000 * r + -0.4542 * g + -0.0468 * b + 128.;
+    [y, u, v].map(|c| c.round().clamp(0., 255.) as u8)
+}
+
+// Deduplication through an ordered map, as preprocessing was first implemented
+fn btree_map_preprocessing(points: &[[f64; 3]], colors: &[[u8; 3]]) -> Cloud {
+    // First index and colors of each location
+    let mut points_map = BTreeMap::<_, (usize, Vec<[u8; 3]>)>::new();
+    for (i, (point, color)) in points.iter().zip(colors).enumerate() {
+        let entry = points_map
+            .entry(point.map(OrderedFloat))
+            .or_insert((i, Vec::new()));
+        entry.1.push(*color);
+    }
+    points_map
+        .values()
+        .map(|(first, group)| {
+            let mut sum = [0.; 3];
+            for color in group {
+                for j in 0..3 {
+                    sum[j] += color[j] as f64;
+                }
+            }
+            let mean = sum.map(|c| (c / group.len() as f64).round() as u8);
+            (points[*first], rgb_to_yuv(mean))
+        })
+        .unzip()
+}
+
+#[test]
+fn duplicates_merge_as_with_an_ordered_map() {
+    let mut rng = SplitMix64::new(3);
+    // Coordinates on a small lattice, so that most locations hold several points, with
+    // both signs of zero
+    let coordinate = |rng: &mut SplitMix64| match rng.next_below(9) {
+        0 => -0.,
+        i => i as f64 / 2. - 2.,
+    };
+    let mut points = Vec::new();
+    let mut colors = Vec::new();
+    for _ in 0..20000 {
+        points.push([
+            coordinate(&mut rng),
+            coordinate(&mut rng),
+            coordinate(&mut rng),
+        ]);
+        colors.push([0; 3].map(|_| rng.next_below(256) as u8));
+    }
+    let (expected_points, expected_colors) = btree_map_preprocessing(&points, &colors);
+    let (points, colors) = preprocessing::preprocess_point_cloud(points, colors);
+    assert!(points.len() < 1000);
+    let bits = |points: &[[f64; 3]]| {
+        points
+            .iter()
+            .map(|point| point.map(f64::to_bits))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(bits(&points), bits(&expected_points));
+    assert_eq!(colors, expected_colors);
+}
+
+#[test]
+#[should_panic(expected = "Points and colors must have the same length.")]
+fn clouds_with_missing_colors_are_rejected() {
+    let (points, mut colors) = voxelized_cloud(0.);
+    colors.pop();
+    preprocessing::preprocess_point_cloud(points, colors);
+}
+
+#[test]
+fn reports_describe_a_regular_grid() {
+    // 10 x 8 grid of spacing 0.5, with every fifth point doubled and one of them three more times
+    let mut points = Vec::new();
+    for x in 0..10 {
+        for y in 0..8 {
+            points.push([1. + x as f64 * 0.5, -2. + y as f64 * 0.5, 3.]);
+        }
+    }
+    let duplicates = points.iter().step_by(5).copied().collect::<Vec<_>>();
+    points.extend(&duplicates);
+    points.extend([points[10]; 3]);
+    let colors = vec![[200, 100, 50]; points.len()];
+    let (points, _, report) =
+        preprocessing::preprocess_point_cloud_with_report(points, colors, None);
+    assert_eq!(report.input_points, 80 + 16 + 3);
+    assert_eq!(report.output_points, 80);
+    assert_eq!(points.len(), 80);
+    assert_eq!(report.merged_duplicates, 16 + 3);
+    assert_eq!(report.max_duplicates, 5);
+    assert_eq!(report.outliers_removed, 0);
+    assert_eq!(report.bounding_box, [[1., -2., 3.], [5.5, 1.5, 3.]]);
+    assert_eq!(report.mean_nn_spacing, 0.5);
+    assert_eq!(report.median_nn_spacing, 0.5);
+    // A single color, counted once per output point in each channel
+    for histogram in report.color_histograms {
+        assert_eq!(histogram.iter().sum::<usize>(), 80);
+        assert_eq!(histogram.iter().filter(|&&count| count > 0).count(), 1);
+    }
+}
@@ -0,0 +1,81 @@
+use pointpca2_rs::progress::{ProgressSink, Stage};
+use pointpca2_rs::{compute_pointpca2_with_config, PointPca2Config};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[derive(Debug, PartialEq)]
+enum Event {
+    Started(Stage),
+    Finished(Stage),
+    Points(usize, usize),
+}
+
+#[derive(Debug, Default)]
+struct Recorder(Mutex<Vec<Event>>);
+
+impl ProgressSink for Recorder {
+    fn stage_started(&self, stage: Stage) {
+        self.0.lock().unwrap().push(Event::Started(stage));
+    }
+
+    fn stage_finished(&self, stage: Stage, _elapsed: Duration) {
+        self.0.lock().unwrap().push(Event::Finished(stage));
+    }
+
+    fn points_processed(&self, processed: usize, total: usize) {
+        self.0.lock().unwrap().push(Event::Points(processed, total));
+    }
+}
+
+fn grid_cloud(offset: f64) -> (Vec<[f64; 3]>, Vec<[u8; 3]>) {
+    let mut points = Vec::new();
+    let mut colors = Vec::new();
+    for x in 0..50 {
+        for y in 0..50 {
+            points.push([x as f64, y as f64, ((x * y) as f64 / 100. + offset).round()]);
+            colors.push([(x * 5) as u8, (y * 5) as u8, 128]);
+        }
+    }
+    (points, colors)
+}
+
+#[test]
+fn stages_and_points_are_reported() {
+    let recorder = Arc::new(Recorder::default());
+    for memory_budget in [None, Some(100_000)] {
+        let config = PointPca2Config {
+            progress: recorder.clone(),
+            memory_budget,
+            ..PointPca2Config::new(27)
+        };
+        let (points_a, colors_a) = grid_cloud(0.);
+        let (points_b, colors_b) = grid_cloud(0.3);
+        compute_pointpca2_with_config(points_a, colors_a, points_b, colors_b, &config).unwrap();
+        let events = std::mem::take(&mut *recorder.0.lock().unwrap());
+        let stages = events
+            .iter()
+            .filter(|event| !matches!(event, Event::Points(..)))
+            .collect::<Vec<_>>();
+        let mut expected = Vec::new();
+        for stage in [Stage::Preprocess, Stage::IndexBuild, Stage::Features] {
+            expected.push(Event::Started(stage));
+            expected.push(Event::Finished(stage));
+        }
+        if memory_budget.is_none() {
+            expected.push(Event::Started(Stage::Predictors));
+        } else {
+            // The predictors of the first chunks are computed between chunks of features
+            expected.insert(5, Event::Started(Stage::Predictors));
+        }
+        expected.push(Event::Finished(Stage::Predictors));
+        assert_eq!(stages, expected.iter().collect::<Vec<_>>());
+        let processed = events
+            .iter()
+            .filter_map(|event| match event {
+                Event::Points(processed, total) => Some((*processed, *total)),
+                _ => None,
+            })
+            .max();
+        assert_eq!(processed, Some((2500, 2500)));
+    }
+}
@@ -0,0 +1,68 @@
+use pointpca2_rs::reduced_reference::{self, ReducedReferenceConfig, Signature};
+use pointpca2_rs::PointPca2Error;
+
+mod common;
+
+use common::{voxelized_cloud, Surface};
+
+fn signature() -> Signature {
+    let (points, colors) = voxelized_cloud(0.);
+    reduced_reference::compute_signature(points, colors, &ReducedReferenceConfig::new(27)).unwrap()
+}
+
+#[test]
+fn identical_clouds_score_one() {
+    let signature = signature();
+    let (points, colors) = voxelized_cloud(0.);
+    let result = reduced_reference::compute_reduced_reference(&signature, points, colors).unwrap();
+    assert_eq!(result.predictor_names.len(), result.predictors.len());
+    assert_eq!(result.predictor_names[0], "color_mean_y");
+    assert_eq!(result.missing_regions, 0);
+    assert_eq!(result.extra_regions, 0);
+    assert!(result.predictors.iter().all(|&x| x == 1.));
+}
+
+#[test]
+fn distortions_lower_their_predictors() {
+    let signature = signature();
+    let predictor = |result: &reduced_reference::ReducedReferenceResult, name: &str| {
+        let j = result
+            .predictor_names
+            .iter()
+            .position(|n| n == name)
+            .unwrap();
+        result.predictors[j]
+    };
+    let (points, colors) = Surface {
+        bump: 2.,
+        ..Default::default()
+    }
+    .cloud();
+    let geometry =
+        reduced_reference::compute_reduced_reference(&signature, points, colors).unwrap();
+    assert!(predictor(&geometry, "eigenvalue_e3") < 0.9);
+    let (points, colors) = Surface {
+        color_shift: 10,
+        ..Default::default()
+    }
+    .cloud();
+    let texture = reduced_reference::compute_reduced_reference(&signature, points, colors).unwrap();
+    // The colors leave the geometry and the regions unchanged
+    assert!(predictor(&texture, "color_mean_y") < 1.);
+    assert_eq!(predictor(&texture, "eigenvalue_e3"), 1.);
+}
+
+#[test]
+fn signatures_round_trip_through_bytes() {
+    let signature = signature();
+    let bytes = signature.to_bytes();
+    assert_eq!(Signature::from_bytes(&bytes), Ok(signature));
+    assert!(matches!(
+        Signature::from_bytes(&bytes[..bytes.len() - 1]),
+        Err(PointPca2Error::InvalidSignature { .. })
+    ));
+    assert!(matches!(
+        Signature::from_bytes(b"not a signature"),
+        Err(PointPca2Error::InvalidSignature { .. })
+    ));
+}
@@ -0,0 +1,178 @@
+use pointpca2_rs::knn_search::Neighborhood;
+use pointpca2_rs::sampling::{self, Bootstrap, QuerySampling};
+use pointpca2_rs::utils::{self, SplitMix64};
+use pointpca2_rs::{compute_pointpca2_multiscale, compute_pointpca2_with_config};
+use pointpca2_rs::{PointPca2Config, PointPca2Error};
+use std::collections::HashSet;
+
+mod common;
+
+use common::{Cloud, Surface};
+
+fn voxelized_cloud(offset: f64) -> Cloud {
+    Surface {
+        size: 40,
+        ..Surface::new(offset)
+    }
+    .cloud()
+}
+
+#[test]
+fn samples_are_sorted_distinct_indices() {
+    let (points, _) = voxelized_cloud(0.);
+    let uniform = QuerySampling::Uniform {
+        count: 100,
+        seed: 7,
+    };
+    let farthest = QuerySampling::FarthestPoint { count: 100 };
+    let voxel = QuerySampling::Voxel { size: 4. };
+    for sampling in [uniform, farthest, voxel] {
+        let sample = sampling::sample_queries(&points, &sampling);
+        assert!(sample.windows(2).all(|w| w[0] < w[1]));
+        assert!(sample.iter().all(|&i| i < points.len()));
+        if sampling != voxel {
+            assert_eq!(sample.len(), 100);
+        }
+    }
+    // One point per occupied voxel
+    let voxels = |indices: &[usize]| {
+        indices
+            .iter()
+            .map(|&i| points[i].map(|x| (x / 4.).floor() as i64))
+            .collect::<HashSet<_>>()
+    };
+    let sample = sampling::sample_queries(&points, &voxel);
+    let all = (0..points.len()).collect::<Vec<_>>();
+    assert_eq!(voxels(&sample).len(), sample.len());
+    assert_eq!(voxels(&sample), voxels(&all));
+}
+
+#[test]
+fn bootstrap_intervals_cover_full_cloud_predictors() {
+    let (points_a, colors_a) = voxelized_cloud(0.);
+    let (points_b, colors_b) = voxelized_cloud(0.4);
+    let config = PointPca2Config::new(27);
+    let full = compute_pointpca2_with_config(
+        points_a.clone(),
+        colors_a.clone(),
+        points_b.clone(),
+        colors_b.clone(),
+        &config,
+    )
+    .unwrap()
+    .predictors;
+    let config = PointPca2Config {
+        sampling: QuerySampling::Uniform {
+            count: 400,
+            seed: 1,
+        },
+        bootstrap: Some(Bootstrap::new(500, 0.95)),
+        ..config
+    };
+    let result =
+        compute_pointpca2_with_config(points_a, colors_a, points_b, colors_b, &config).unwrap();
+    let intervals = result.confidence_intervals.unwrap();
+    let covered = (0..full.len())
+        .filter(|&j| intervals.lower[j] <= full[j] && full[j] <= intervals.upper[j])
+        .count();
+    assert!(covered >= 36, "{} of {} covered", covered, full.len());
+    for j in 0..full.len() {
+        assert!(intervals.lower[j] <= result.predictors[j]);
+        assert!(result.predictors[j] <= intervals.upper[j]);
+    }
+}
+
+// Partial Fisher-Yates shuffle of the whole index range
+fn dense_sample_indices(n: usize, sample_size: usize, seed: u64) -> Vec<usize> {
+    let mut rng = SplitMix64::new(seed);
+    let mut indices = (0..n).collect::<Vec<usize>>();
+    let sample_size = sample_size.min(n);
+    for i in 0..sample_size {
+        let j = i + rng.next_below(n - i);
+        indices.swap(i, j);
+    }
+    indices.truncate(sample_size);
+    indices.sort_unstable();
+    indices
+}
+
+#[test]
+fn sparse_sampling_draws_the_dense_shuffle_sample() {
+    for (n, sample_size) in [
+        (0, 5),
+        (10, 10),
+        (10, 20),
+        (1000, 1),
+        (1000, 999),
+        (100_000, 300),
+    ] {
+        for seed in [0, 7, 42] {
+            assert_eq!(
+                utils::sample_indices(n, sample_size, seed),
+                dense_sample_indices(n, sample_size, seed),
+                "{} of {} with seed {}",
+                sample_size,
+                n,
+                seed
+            );
+        }
+    }
+}
+
+#[test]
+fn invalid_sampling_and_neighborhoods_are_rejected() {
+    let run = |config: &PointPca2Config| {
+        let (points_a, colors_a) = voxelized_cloud(0.);
+        let (points_b, colors_b) = voxelized_cloud(0.4);
+        compute_pointpca2_with_config(points_a, colors_a, points_b, colors_b, config)
+    };
+    let samplings = [
+        QuerySampling::Uniform { count: 0, seed: 0 },
+        QuerySampling::FarthestPoint { count: 0 },
+        QuerySampling::Voxel { size: 0. },
+        QuerySampling::Voxel { size: -1. },
+        QuerySampling::Voxel { size: f64::NAN },
+    ];
+    for sampling in samplings {
+        let config = PointPca2Config {
+            sampling,
+            ..PointPca2Config::new(27)
+        };
+        let error = run(&config).err();
+        assert!(
+            matches!(error, Some(PointPca2Error::InvalidConfig { .. })),
+            "{:?}: {:?}",
+            sampling,
+            error
+        );
+    }
+    let neighborhoods = [
+        Neighborhood::Knn(0),
+        Neighborhood::Radius(0.),
+        Neighborhood::Hybrid {
+            k: 27,
+            radius: f64::NAN,
+            min_count: 1,
+        },
+    ];
+    for neighborhood in neighborhoods {
+        let config = PointPca2Config {
+            neighborhood,
+            ..Default::default()
+        };
+        let error = run(&config).err();
+        assert!(
+            matches!(error, Some(PointPca2Error::InvalidConfig { .. })),
+            "{:?}: {:?}",
+            neighborhood,
+            error
+        );
+    }
+    let (points_a, colors_a) = voxelized_cloud(0.);
+    let (points_b, colors_b) = voxelized_cloud(0.4);
+    let config = PointPca2Config::default();
+    let error =
+        compute_pointpca2_multiscale(points_a, colors_a, points_b, colors_b, &[27, 0], &config)
+            .err();
+    assert!(matches!(error, Some(PointPca2Error::InvalidConfig { .. })));
+}
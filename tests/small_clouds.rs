@@ -0,0 +1,60 @@
+use pointpca2_rs::knn_search::SmallCloudPolicy;
+use pointpca2_rs::{compute_pointpca2, compute_pointpca2_with_config};
+use pointpca2_rs::{PointPca2Config, PointPca2Error, PointPca2Result};
+
+mod common;
+
+use common::{voxelized_cloud, Surface};
+
+// A 4 x 4 reference against the default distorted surface
+fn run(search_size: usize, policy: SmallCloudPolicy) -> Result<PointPca2Result, PointPca2Error> {
+    let (points_a, colors_a) = Surface {
+        size: 4,
+        ..Default::default()
+    }
+    .cloud();
+    let (points_b, colors_b) = voxelized_cloud(0.4);
+    let config = PointPca2Config {
+        small_cloud_policy: policy,
+        ..PointPca2Config::new(search_size)
+    };
+    compute_pointpca2_with_config(points_a, colors_a, points_b, colors_b, &config)
+}
+
+#[test]
+fn error_policy_rejects_small_clouds() {
+    assert_eq!(
+        run(27, SmallCloudPolicy::Error).err(),
+        Some(PointPca2Error::CloudSmallerThanNeighborhood {
+            cloud: "reference",
+            points: 16,
+            neighborhood_size: 27,
+        })
+    );
+}
+
+#[test]
+fn clamp_policy_uses_the_smaller_cloud_size() {
+    let clamped = run(27, SmallCloudPolicy::Clamp).unwrap();
+    assert_eq!(clamped.small_cloud_policy, Some(SmallCloudPolicy::Clamp));
+    let expected = run(16, SmallCloudPolicy::Error).unwrap();
+    assert_eq!(expected.small_cloud_policy, None);
+    assert_eq!(clamped.predictors, expected.predictors);
+}
+
+#[test]
+fn pad_policy_keeps_the_neighborhood_size() {
+    let padded = run(27, SmallCloudPolicy::Pad).unwrap();
+    assert_eq!(padded.small_cloud_policy, Some(SmallCloudPolicy::Pad));
+    assert!(padded.predictors.iter().all(|x| x.is_finite()));
+    // Repeated neighbors change the statistics of the neighborhoods
+    let clamped = run(27, SmallCloudPolicy::Clamp).unwrap();
+    assert_ne!(padded.predictors, clamped.predictors);
+}
+
+#[test]
+#[should_panic(expected = "Failure when computing PointPCA2.")]
+fn legacy_api_panics_on_empty_clouds() {
+    let (points, colors) = voxelized_cloud(0.);
+    compute_pointpca2(Vec::new(), Vec::new(), points, colors, 27, false);
+}